@@ -1,4 +1,6 @@
 use std::{
+    collections::HashMap,
+    hash::Hash,
     ops::{Not, Sub},
     sync::LazyLock,
     time::{Duration, Instant},
@@ -19,6 +21,7 @@ use tauri_plugin_egui::{
         Color32,
         ComboBox,
         Context,
+        DragValue,
         Layout,
         ScrollArea,
         SidePanel,
@@ -30,13 +33,17 @@ use tauri_plugin_egui::{
     AppHandleExt,
 };
 use wooting_profile_switcher as wps;
-use wps::{DeviceIndices, ProfileIndex};
+use wps::{Device, DeviceIndices, DeviceSerial, LightingMode, Pending, ProfileIndex, RgbColor};
 
 use crate::{
-    config::{Config, Rule},
+    config::{Config, MatchGroup, MatchMode, MatchStrategy, Rule},
+    report_device_error,
+    rules::{self, Lint, MatchPattern},
     theme::Theme,
+    window_list::{self, OpenWindow},
     ActiveMatchInfo,
     Args,
+    DeviceState,
 };
 
 const CARGO_PKG_DESCRIPTION: &str = env!("CARGO_PKG_DESCRIPTION");
@@ -72,25 +79,39 @@ static GAMES: LazyLock<Vec<Game>> = LazyLock::new(|| {
 
 #[derive(Clone, Debug)]
 struct SelectedRule {
-    alias:          String,
-    device_indices: DeviceIndices,
-    match_app_name: String,
-    match_bin_name: String,
-    match_bin_path: String,
-    match_win_name: String,
-    rule_index:     usize,
+    alias:           String,
+    device_indices:  DeviceIndices,
+    match_app_name:  String,
+    match_bin_name:  String,
+    match_bin_path:  String,
+    match_win_name:  String,
+    match_mode:      MatchMode,
+    match_groups:    Vec<MatchGroup>,
+    priority:        i32,
+    switch_delay_ms: Option<u64>,
+    lighting_mode:   Option<LightingMode>,
+    lighting_modes:  HashMap<DeviceSerial, LightingMode>,
+    overlay:         HashMap<String, RgbColor>,
+    rule_index:      usize,
 }
 
 impl SelectedRule {
     fn new(rule: Rule, i: usize) -> Self {
         Self {
-            alias:          rule.alias,
-            device_indices: rule.device_indices,
-            match_app_name: rule.match_app_name.unwrap_or_default(),
-            match_bin_name: rule.match_bin_name.unwrap_or_default(),
-            match_bin_path: rule.match_bin_path.unwrap_or_default(),
-            match_win_name: rule.match_win_name.unwrap_or_default(),
-            rule_index:     i,
+            alias:           rule.alias,
+            device_indices:  rule.device_indices,
+            match_app_name:  rule.match_app_name.unwrap_or_default(),
+            match_bin_name:  rule.match_bin_name.unwrap_or_default(),
+            match_bin_path:  rule.match_bin_path.unwrap_or_default(),
+            match_win_name:  rule.match_win_name.unwrap_or_default(),
+            match_mode:      rule.match_mode,
+            match_groups:    rule.match_groups,
+            priority:        rule.priority,
+            switch_delay_ms: rule.switch_delay_ms,
+            lighting_mode:   rule.lighting_mode,
+            lighting_modes:  rule.lighting_modes,
+            overlay:         rule.overlay,
+            rule_index:      i,
         }
     }
 }
@@ -98,43 +119,64 @@ impl SelectedRule {
 impl From<SelectedRule> for Rule {
     fn from(rule: SelectedRule) -> Self {
         Self {
-            alias:          rule.alias,
-            device_indices: rule.device_indices,
-            match_app_name: rule
+            alias:           rule.alias,
+            device_indices:  rule.device_indices,
+            match_app_name:  rule
                 .match_app_name
                 .is_empty()
                 .not()
                 .then_some(rule.match_app_name),
-            match_bin_name: rule
+            match_bin_name:  rule
                 .match_bin_name
                 .is_empty()
                 .not()
                 .then_some(rule.match_bin_name),
-            match_bin_path: rule
+            match_bin_path:  rule
                 .match_bin_path
                 .is_empty()
                 .not()
                 .then_some(rule.match_bin_path),
-            match_win_name: rule
+            match_win_name:  rule
                 .match_win_name
                 .is_empty()
                 .not()
                 .then_some(rule.match_win_name),
+            match_mode:      rule.match_mode,
+            match_groups:    rule.match_groups,
+            priority:        rule.priority,
+            switch_delay_ms: rule.switch_delay_ms,
+            lighting_mode:   rule.lighting_mode,
+            lighting_modes:  rule.lighting_modes,
+            overlay:         rule.overlay,
         }
     }
 }
 
+/// A profile switch queued on the device worker
+#[derive(Debug)]
+struct PendingSwitch {
+    device_serial: DeviceSerial,
+    profile_index: ProfileIndex,
+    pending:       Pending<()>,
+}
+
 #[allow(clippy::module_name_repetitions)]
 #[allow(clippy::struct_excessive_bools)]
 pub struct MainApp {
-    open_auto_launch:    bool,
-    open_auto_update:    bool,
+    open_auto_launch: bool,
+    open_auto_update: bool,
     open_new_rule_setup: bool,
     open_confirm_delete: bool,
-    selected_rule:       Option<SelectedRule>,
-    base_style:          Option<egui::Style>,
-    last_scale:          f32,
-    last_theme:          Option<Theme>,
+    open_window_picker: bool,
+    open_windows: Result<Vec<OpenWindow>, String>,
+    window_filter: String,
+    lints: Vec<Lint>,
+    lints_revision: Option<u64>,
+    selected_rule: Option<SelectedRule>,
+    pending_switch: Option<PendingSwitch>,
+    base_style: Option<egui::Style>,
+    last_scale: f32,
+    last_theme: Option<Theme>,
 }
 
 impl MainApp {
@@ -201,14 +243,20 @@ impl MainApp {
     pub fn new(app: &AppHandle) -> Self {
         let config = app.state::<RwLock<Config>>().read().clone();
         Self {
-            open_auto_launch:    config.auto_launch.is_none(),
-            open_auto_update:    config.auto_update.is_none(),
+            open_auto_launch: config.auto_launch.is_none(),
+            open_auto_update: config.auto_update.is_none(),
             open_new_rule_setup: false,
             open_confirm_delete: false,
-            selected_rule:       None,
-            base_style:          None,
-            last_scale:          1.0,
-            last_theme:          None,
+            open_window_picker: false,
+            open_windows: Ok(Vec::new()),
+            window_filter: String::new(),
+            lints: Vec::new(),
+            lints_revision: None,
+            selected_rule: None,
+            pending_switch: None,
+            base_style: None,
+            last_scale: 1.0,
+            last_theme: None,
         }
     }
 }
@@ -433,11 +481,98 @@ impl MainApp {
             });
     }
 
+    fn refresh_open_windows(&mut self) {
+        self.open_windows = window_list::open_windows().map_err(|error| error.to_string());
+    }
+
+    fn render_window_picker_popup(&mut self, ctx: &Context) {
+        if !self.open_window_picker {
+            return;
+        }
+
+        let mut open = true;
+        let mut picked = None;
+        Window::new("Pick Window")
+            .collapsible(false)
+            .resizable(true)
+            .open(&mut open)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Filter:");
+                    ui.text_edit_singleline(&mut self.window_filter);
+                    if ui.button("Refresh").clicked() {
+                        self.refresh_open_windows();
+                    }
+                });
+                ui.separator();
+
+                let windows = match &self.open_windows {
+                    Ok(windows) => windows,
+                    Err(error) => {
+                        ui.colored_label(Color32::RED, error);
+                        return;
+                    }
+                };
+
+                let filter = self.window_filter.to_lowercase();
+                ScrollArea::vertical().id_salt("windows").show(ui, |ui| {
+                    for window in windows.iter().filter(|window| {
+                        [&window.win_name, &window.bin_name, &window.bin_path]
+                            .into_iter()
+                            .chain(&window.class)
+                            .any(|text| text.to_lowercase().contains(&filter))
+                    }) {
+                        ui.group(|ui| {
+                            ui.horizontal(|ui| {
+                                if ui.button("Use").clicked() {
+                                    picked = Some(window.clone());
+                                }
+                                ui.strong(&window.win_name);
+                            });
+                            ui.horizontal(|ui| {
+                                ui.label("Process:");
+                                ui.monospace(&window.bin_name);
+                                ui.label("PID:");
+                                ui.monospace(window.pid.to_string());
+                                if let Some(class) = &window.class {
+                                    ui.label("Class:");
+                                    ui.monospace(class);
+                                }
+                            });
+                            ui.monospace(&window.bin_path);
+                        });
+                    }
+                });
+            });
+
+        // Exact patterns, so titles and paths containing wildcards match only themselves
+        if let Some(window) = picked {
+            if let Some(selected_rule) = self.selected_rule.as_mut() {
+                let exact = |value: &str| {
+                    if value.is_empty() {
+                        String::new()
+                    } else {
+                        MatchPattern::exact(value).to_string()
+                    }
+                };
+
+                selected_rule.match_app_name = exact(&window.app_name);
+                selected_rule.match_bin_name = exact(&window.bin_name);
+                selected_rule.match_bin_path = exact(&window.bin_path);
+                selected_rule.match_win_name = exact(&window.win_name);
+            }
+            open = false;
+        }
+
+        self.open_window_picker = open;
+    }
+
     fn render_popups(&mut self, ctx: &Context, app: &AppHandle, config: &RwLock<Config>) {
         self.render_auto_launch_popup(ctx, app, config);
         self.render_auto_update_popup(ctx, config);
         self.render_new_rule_popup(ctx, config);
         self.render_confirm_delete_popup(ctx, config);
+        self.render_window_picker_popup(ctx);
     }
 
     fn render_header_controls(ui: &mut Ui, args: &RwLock<Args>, config: &RwLock<Config>) {
@@ -469,7 +604,7 @@ impl MainApp {
                 open::that(config_path).expect("Failed to open config file");
             }
             if ui.button("Reload Config").clicked() {
-                *config.write() = Config::load().expect("Failed to reload config");
+                *config.write() = Config::load_and_apply().expect("Failed to reload config");
             }
             ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
                 let current_theme = config.read().ui.theme;
@@ -492,11 +627,52 @@ impl MainApp {
         });
     }
 
-    fn render_keyboard_switcher(ui: &mut Ui, args: &RwLock<Args>, config: &RwLock<Config>) {
-        let devices = config.read().devices.clone();
+    fn poll_pending_switch(&mut self, device_state: &RwLock<DeviceState>) {
+        let Some(result) = self
+            .pending_switch
+            .as_ref()
+            .and_then(|switch| switch.pending.try_wait())
+        else {
+            return;
+        };
+
+        let Some(switch) = self.pending_switch.take() else {
+            return;
+        };
+
+        if let Err(error) = result {
+            report_device_error(&error);
+            return;
+        }
+
+        device_state
+            .write()
+            .profile_indices
+            .insert(switch.device_serial, switch.profile_index);
+    }
+
+    fn render_keyboard_switcher(
+        &mut self,
+        ui: &mut Ui,
+        config: &RwLock<Config>,
+        device_state: &RwLock<DeviceState>,
+    ) {
+        let (connected, profile_indices) = {
+            let device_state = device_state.read();
+            (
+                device_state.connected.clone(),
+                device_state.profile_indices.clone(),
+            )
+        };
+        let devices = config
+            .read()
+            .devices
+            .iter()
+            .filter(|(device_serial, _)| connected.contains(device_serial))
+            .map(|(device_serial, device)| (device_serial.clone(), device.clone()))
+            .collect::<Vec<_>>();
         let show_serial = config.read().show_serial;
-        let send_sleep_ms = config.read().send_sleep_ms;
-        let swap_lighting = config.read().swap_lighting;
+        let switch_options = config.read().switch_options();
 
         if devices.is_empty() {
             ui.label("No devices detected.");
@@ -516,29 +692,44 @@ impl MainApp {
                             &device.model_name
                         };
 
+                        let active_profile_index = profile_indices
+                            .get(&device_serial)
+                            .and_then(|profile_index| usize::try_from(*profile_index).ok());
+
                         ui.group(|ui| {
                             ui.vertical(|ui| {
                                 ui.label(text);
+                                if let Some(details) = Self::device_details(&device) {
+                                    ui.small(details);
+                                }
                                 ui.horizontal_wrapped(|ui| {
                                     for (profile_index, profile_name) in
-                                        device.profiles.iter().enumerate()
+                                        device.available_profiles().iter().enumerate()
                                     {
-                                        if ui.button(profile_name).clicked() {
-                                            if wps::select_device_serial(&device_serial).is_err() {
-                                                return;
-                                            }
-
+                                        // Only one switch at a time, the buttons are disabled until it completes
+                                        let enabled = self.pending_switch.is_none();
+                                        let selected = active_profile_index == Some(profile_index);
+                                        let button = Button::new(profile_name).selected(selected);
+                                        if ui.add_enabled(enabled, button).clicked() {
                                             #[allow(clippy::cast_possible_truncation)]
                                             let profile_index = profile_index as ProfileIndex;
-                                            let _ = wps::set_active_profile_index(
+                                            let pending = wps::submit({
+                                                let device_serial = device_serial.clone();
+                                                let switch_options = switch_options.clone();
+                                                move |manager| {
+                                                    manager.set_profile_index(
+                                                        &device_serial,
+                                                        profile_index,
+                                                        &switch_options,
+                                                    )
+                                                }
+                                            });
+
+                                            self.pending_switch = Some(PendingSwitch {
+                                                device_serial: device_serial.clone(),
                                                 profile_index,
-                                                send_sleep_ms,
-                                                swap_lighting,
-                                            );
-
-                                            let mut args = args.write();
-                                            args.device_serial = Some(device_serial.clone());
-                                            args.profile_index = Some(profile_index);
+                                                pending,
+                                            });
                                         }
                                     }
                                 });
@@ -549,7 +740,13 @@ impl MainApp {
             });
     }
 
-    fn render_top_panel(&mut self, ctx: &Context, args: &RwLock<Args>, config: &RwLock<Config>) {
+    fn render_top_panel(
+        &mut self,
+        ctx: &Context,
+        args: &RwLock<Args>,
+        config: &RwLock<Config>,
+        device_state: &RwLock<DeviceState>,
+    ) {
         TopBottomPanel::top("top_panel").show(ctx, |ui| {
             ui.add_space(6.0);
             Self::render_header_controls(ui, args, config);
@@ -558,13 +755,23 @@ impl MainApp {
             ui.separator();
             ui.add_space(4.0);
 
-            Self::render_keyboard_switcher(ui, args, config);
+            self.render_keyboard_switcher(ui, config, device_state);
 
             ui.add_space(6.0);
         });
     }
 
+    // Lints the saved rules again after the config was loaded or saved
+    fn update_lints(&mut self, config: &RwLock<Config>) {
+        let revision = Config::revision();
+        if self.lints_revision != Some(revision) {
+            self.lints = rules::lint(&config.read());
+            self.lints_revision = Some(revision);
+        }
+    }
+
     fn render_rules_panel(&mut self, ctx: &Context, config: &RwLock<Config>) {
+        self.update_lints(config);
         SidePanel::left("side_panel")
             .resizable(false)
             .show(ctx, |ui| {
@@ -608,6 +815,17 @@ impl MainApp {
                                 config.save().expect("Failed to move rule down");
                             }
 
+                            let lints = self
+                                .lints
+                                .iter()
+                                .filter(|lint| lint.rule_index == i)
+                                .map(|lint| lint.kind.to_string())
+                                .collect::<Vec<_>>();
+                            if !lints.is_empty() {
+                                ui.colored_label(Color32::from_rgb(220, 178, 48), "⚠")
+                                    .on_hover_text(lints.join("\n"));
+                            }
+
                             let mut button = Button::new(&rule.alias);
                             if let Some(rule) = &self.selected_rule {
                                 if rule.rule_index == i {
@@ -692,7 +910,7 @@ impl MainApp {
                 ui.label("Match App Name");
             });
             row.col(|ui| {
-                ui.text_edit_singleline(&mut selected_rule.match_app_name);
+                Self::render_pattern_edit(ui, &mut selected_rule.match_app_name);
             });
         });
 
@@ -701,7 +919,7 @@ impl MainApp {
                 ui.label("Match Bin Name");
             });
             row.col(|ui| {
-                ui.text_edit_singleline(&mut selected_rule.match_bin_name);
+                Self::render_pattern_edit(ui, &mut selected_rule.match_bin_name);
             });
         });
 
@@ -710,7 +928,7 @@ impl MainApp {
                 ui.label("Match Bin Path");
             });
             row.col(|ui| {
-                ui.text_edit_singleline(&mut selected_rule.match_bin_path);
+                Self::render_pattern_edit(ui, &mut selected_rule.match_bin_path);
             });
         });
 
@@ -719,9 +937,160 @@ impl MainApp {
                 ui.label("Match Win Name");
             });
             row.col(|ui| {
-                ui.text_edit_singleline(&mut selected_rule.match_win_name);
+                Self::render_pattern_edit(ui, &mut selected_rule.match_win_name);
             });
         });
+
+        body.row(height, |mut row| {
+            row.col(|ui| {
+                ui.label("Match Mode");
+            });
+            row.col(|ui| {
+                ui.horizontal(|ui| {
+                    let match_mode = &mut selected_rule.match_mode;
+                    ui.selectable_value(match_mode, MatchMode::Any, "Any field")
+                        .on_hover_text("Match when any field or group matches");
+                    ui.selectable_value(match_mode, MatchMode::All, "All fields")
+                        .on_hover_text("Match when every field and group matches");
+
+                    // Groups are only editable in the config file, but kept on save
+                    let groups = selected_rule.match_groups.len();
+                    if groups > 0 {
+                        ui.weak(format!("+ {groups} condition group(s) from the config"));
+                    }
+                });
+            });
+        });
+
+        body.row(height, |mut row| {
+            row.col(|ui| {
+                ui.label("Priority");
+            });
+            row.col(|ui| {
+                ui.add(DragValue::new(&mut selected_rule.priority))
+                    .on_hover_text(
+                    "Breaks ties between equally specific rules, higher wins, only used by the \
+                     Most specific rule matching",
+                );
+            });
+        });
+
+        body.row(height, |mut row| {
+            row.col(|ui| {
+                ui.label("Switch Delay");
+            });
+            row.col(|ui| {
+                ui.horizontal(|ui| {
+                    let mut overridden = selected_rule.switch_delay_ms.is_some();
+                    if ui
+                        .checkbox(&mut overridden, "Override")
+                        .on_hover_text(
+                            "How long the window must keep focus before this rule is applied, \
+                             instead of the global switch delay",
+                        )
+                        .changed()
+                    {
+                        selected_rule.switch_delay_ms = overridden.then_some(0);
+                    }
+
+                    if let Some(switch_delay_ms) = &mut selected_rule.switch_delay_ms {
+                        ui.add(DragValue::new(switch_delay_ms).speed(10).suffix(" ms"));
+                    }
+                });
+            });
+        });
+
+        body.row(height, |mut row| {
+            row.col(|ui| {
+                ui.label("Lighting Mode");
+            });
+            row.col(|ui| {
+                let lighting_mode = &mut selected_rule.lighting_mode;
+                Self::render_lighting_mode_select(
+                    ui,
+                    "rule_lighting_select",
+                    lighting_mode,
+                    "Default",
+                );
+            });
+        });
+    }
+
+    // The strategy picking the rule among the matching rules, saved right away
+    fn render_match_strategy_select(ui: &mut Ui, config: &RwLock<Config>) {
+        let current_strategy = config.read().match_strategy;
+        ui.label("Rule Matching:");
+        ComboBox::from_id_salt("match_strategy_select")
+            .selected_text(current_strategy.display_name())
+            .show_ui(ui, |ui| {
+                for strategy in MatchStrategy::ALL {
+                    let selected = strategy == current_strategy;
+                    if ui
+                        .selectable_label(selected, strategy.display_name())
+                        .clicked()
+                    {
+                        let mut config = config.write();
+                        config.match_strategy = strategy;
+                        config.save().expect("Failed to save config");
+                    }
+                }
+            })
+            .response
+            .on_hover_text(match current_strategy {
+                MatchStrategy::FirstMatch => "The first matching rule in the list wins",
+                MatchStrategy::MostSpecific => {
+                    "The matching rule with the most exact, then non-wildcard fields wins, then \
+                     the highest priority, then the first in the list"
+                }
+            });
+    }
+
+    // A match pattern field and the kind of pattern it's interpreted as
+    fn render_pattern_edit(ui: &mut Ui, pattern: &mut String) {
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(pattern);
+            if pattern.is_empty() {
+                return;
+            }
+
+            let pattern = MatchPattern::from(pattern.as_str());
+            let kind = pattern.kind.label();
+            if pattern.case_insensitive {
+                ui.weak(format!("{kind}, ignoring case"));
+            } else {
+                ui.weak(kind);
+            }
+        });
+    }
+
+    // A lighting mode dropdown where None falls back to the default, returns whether it changed
+    fn render_lighting_mode_select(
+        ui: &mut Ui,
+        id_salt: impl Hash,
+        lighting_mode: &mut Option<LightingMode>,
+        default_label: &str,
+    ) -> bool {
+        let selected_text =
+            lighting_mode.map_or_else(|| default_label.to_string(), |mode| mode.to_string());
+        let mut changed = false;
+        ComboBox::from_id_salt(id_salt)
+            .selected_text(selected_text)
+            .show_ui(ui, |ui| {
+                changed |= ui
+                    .selectable_value(lighting_mode, None, default_label)
+                    .changed();
+                for mode in [
+                    LightingMode::Both,
+                    LightingMode::ProfileOnly,
+                    LightingMode::LightingOnly,
+                ] {
+                    changed |= ui
+                        .selectable_value(lighting_mode, Some(mode), mode.to_string())
+                        .changed();
+                }
+            });
+
+        changed
     }
 
     fn render_rule_device_header(body: &mut TableBody<'_>, height: f32, config: &RwLock<Config>) {
@@ -739,6 +1108,30 @@ impl MainApp {
         });
     }
 
+    // Unknown models, firmware version and profile slots, if the keyboard reported them
+    fn device_details(device: &Device) -> Option<String> {
+        let mut details = Vec::new();
+        if wps::keyboard_type(device).is_none() {
+            details.push(String::from("Unknown model"));
+        }
+        if let Some(firmware_version) = device.firmware_version {
+            details.push(format!("Firmware {firmware_version}"));
+        }
+
+        match (device.digital_profile_count, device.analog_profile_count) {
+            (Some(digital), Some(analog)) => {
+                details.push(format!("{digital} digital, {analog} analog profile(s)"));
+            }
+            _ => {
+                if let Some(profile_count) = device.profile_slots() {
+                    details.push(format!("{profile_count} profile(s)"));
+                }
+            }
+        }
+
+        (!details.is_empty()).then(|| details.join(" · "))
+    }
+
     fn render_rule_device_rows(
         body: &mut TableBody<'_>,
         height: f32,
@@ -748,10 +1141,11 @@ impl MainApp {
         let devices = config.read().devices.clone();
         for (device_serial, device) in devices {
             let profile_index = selected_rule.device_indices.get_mut(&device_serial);
+            // Devices the rule doesn't list fall through to the next matching rule, like skipped ones
             if profile_index.is_none() {
                 selected_rule
                     .device_indices
-                    .insert(device_serial.clone(), 0);
+                    .insert(device_serial.clone(), -1);
                 continue;
             }
 
@@ -766,35 +1160,65 @@ impl MainApp {
                 });
                 row.col(|ui| {
                     let profile_index = profile_index.unwrap();
+                    let profiles = device.available_profiles();
                     let selected_text = if *profile_index == -1 {
                         "Skip".to_string()
+                    } else if device.check_profile_index(*profile_index).is_err() {
+                        format!("Index {profile_index} (not on keyboard)")
                     } else {
                         let index = usize::try_from(*profile_index).ok();
                         index
-                            .and_then(|idx| device.profiles.get(idx))
+                            .and_then(|idx| profiles.get(idx))
                             .cloned()
                             .unwrap_or_else(|| format!("Index {profile_index}"))
                     };
 
-                    ComboBox::from_id_salt(("profile_select", &device_serial))
-                        .selected_text(selected_text)
-                        .show_ui(ui, |ui| {
-                            if ui.selectable_label(*profile_index == -1, "Skip").clicked() {
-                                *profile_index = -1;
-                            }
-
-                            for (idx, name) in device.profiles.iter().enumerate() {
-                                let Ok(idx_i8) = i8::try_from(idx) else {
-                                    continue;
-                                };
+                    ui.horizontal(|ui| {
+                        ComboBox::from_id_salt(("profile_select", &device_serial))
+                            .selected_text(selected_text)
+                            .show_ui(ui, |ui| {
                                 if ui
-                                    .selectable_label(*profile_index == idx_i8, name)
+                                    .selectable_label(*profile_index == -1, "Skip")
+                                    .on_hover_text("Use the next matching rule for this keyboard")
                                     .clicked()
                                 {
-                                    *profile_index = idx_i8;
+                                    *profile_index = -1;
+                                }
+
+                                for (idx, name) in profiles.iter().enumerate() {
+                                    let Ok(idx_i8) = i8::try_from(idx) else {
+                                        continue;
+                                    };
+                                    if ui
+                                        .selectable_label(*profile_index == idx_i8, name)
+                                        .clicked()
+                                    {
+                                        *profile_index = idx_i8;
+                                    }
+                                }
+                            });
+
+                        let mut lighting_mode =
+                            selected_rule.lighting_modes.get(&device_serial).copied();
+                        let id_salt = ("lighting_select", &device_serial);
+                        if Self::render_lighting_mode_select(
+                            ui,
+                            id_salt,
+                            &mut lighting_mode,
+                            "Rule default",
+                        ) {
+                            match lighting_mode {
+                                Some(lighting_mode) => {
+                                    selected_rule
+                                        .lighting_modes
+                                        .insert(device_serial.clone(), lighting_mode);
+                                }
+                                None => {
+                                    selected_rule.lighting_modes.remove(&device_serial);
                                 }
                             }
-                        });
+                        }
+                    });
                 });
             });
         }
@@ -806,15 +1230,34 @@ impl MainApp {
         config: &RwLock<Config>,
         selected_rule: &SelectedRule,
     ) {
+        // Targets beyond the profile slots of a keyboard would fail on every switch
+        let out_of_range = {
+            let config = config.read();
+            selected_rule
+                .device_indices
+                .iter()
+                .any(|(device_serial, &profile_index)| {
+                    profile_index >= 0
+                        && config.devices.get(device_serial).is_some_and(|device| {
+                            device.check_profile_index(profile_index).is_err()
+                        })
+                })
+        };
+
         body.row(height, |mut row| {
             row.col(|ui| {
-                if ui.button("Save").clicked() {
+                if ui.add_enabled(!out_of_range, Button::new("Save")).clicked() {
                     let rule = selected_rule.clone().into();
                     let mut config = config.write();
                     config.rules[selected_rule.rule_index] = rule;
                     config.save().expect("Failed to save config");
                 }
             });
+            row.col(|ui| {
+                if out_of_range {
+                    ui.colored_label(Color32::LIGHT_RED, "Profile not on keyboard");
+                }
+            });
         });
     }
 
@@ -841,6 +1284,7 @@ impl MainApp {
         config: &RwLock<Config>,
         active_info: &RwLock<ActiveMatchInfo>,
     ) {
+        let mut pick_window = false;
         CentralPanel::default().show(ctx, |ui| {
             let Some(selected_rule) = self.selected_rule.as_mut() else {
                 ui.heading("No rule selected");
@@ -848,22 +1292,41 @@ impl MainApp {
             };
 
             Self::render_active_window_info(ui, active_info, selected_rule);
+            ui.horizontal(|ui| {
+                pick_window = ui
+                    .button("Pick Window")
+                    .on_hover_text("Fill the match variables from an open window")
+                    .clicked();
+                ui.separator();
+                Self::render_match_strategy_select(ui, config);
+            });
 
             ui.add_space(6.0);
-            ui.colored_label(Color32::KHAKI, "Match variables support Wildcard and Regex");
+            ui.colored_label(
+                Color32::KHAKI,
+                "Match variables support Wildcard and Regex, prefix them with glob:, re: or \
+                 exact: to pick one, or glob/i:, re/i: or exact/i: to also ignore case",
+            );
 
             Self::render_rule_fields_table(ui, config, selected_rule);
         });
+
+        if pick_window {
+            self.open_window_picker = true;
+            self.refresh_open_windows();
+        }
     }
 
     fn update(&mut self, ctx: &Context, app: &AppHandle, _delta: Duration) {
         let args = app.state::<RwLock<Args>>();
         let config = app.state::<RwLock<Config>>();
         let active_info = app.state::<RwLock<ActiveMatchInfo>>();
+        let device_state = app.state::<RwLock<DeviceState>>();
 
         self.apply_theme(ctx, &config);
+        self.poll_pending_switch(&device_state);
         self.render_popups(ctx, app, &config);
-        self.render_top_panel(ctx, &args, &config);
+        self.render_top_panel(ctx, &args, &config, &device_state);
         self.render_rules_panel(ctx, &config);
         self.render_rule_editor(ctx, &config, &active_info);
     }
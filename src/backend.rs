@@ -0,0 +1,514 @@
+#[cfg(feature = "rgb")]
+use std::ffi::CStr;
+use std::{collections::VecDeque, sync::Arc};
+
+use parking_lot::Mutex;
+#[cfg(feature = "rgb")]
+use wooting_rgb_sys as rgb;
+
+use crate::{Command, Device, FirmwareVersion, Frame, FrameLayout, KeyColor};
+#[cfg(feature = "rgb")]
+use crate::{RGB_COLUMNS, RGB_ROWS};
+
+/* Constants */
+
+pub const SIMULATED_PROFILE_COUNT: u8 = 4;
+const SIMULATED_RESPONSE_SIZE: usize = 256;
+const SIMULATED_COMMAND_LOG_SIZE: usize = 1024;
+const SIMULATED_MODEL_NAMES: [&str; 4] = [
+    "Wooting 60HE",
+    "Wooting Two HE",
+    "Wooting 80HE",
+    "Wooting UwU RGB",
+];
+
+/* Traits */
+
+/// Low level access to the Wooting feature report interface
+///
+/// The device selected with `select_device` is the target of every following command,
+/// mirroring the global state of the Wooting RGB SDK.
+pub trait DeviceBackend: Send {
+    /// Drop any open handles and enumerate the connected keyboards
+    fn find_devices(&mut self) -> bool;
+
+    /// Count the connected keyboards without sending them any command
+    fn device_count(&mut self) -> u8;
+
+    /// Select the keyboard at the given slot
+    fn select_device(&mut self, device_index: u8) -> bool;
+
+    /// Send a command without waiting for a response
+    fn send_feature(&mut self, command: u8, parameters: [u8; 4]) -> bool;
+
+    /// Send a command and read the response into the buffer, returning the bytes read
+    fn send_feature_with_response(
+        &mut self,
+        buffer: &mut [u8],
+        command: u8,
+        parameters: [u8; 4],
+    ) -> Option<usize>;
+
+    /// Size of a full response report for the selected keyboard
+    fn response_size(&self) -> usize;
+
+    /// Whether the selected keyboard prefixes responses with a report ID
+    fn uses_multi_report(&self) -> bool;
+
+    /// Whether the selected keyboard uses the V2 interface
+    fn uses_v2_interface(&self) -> bool;
+
+    /// Model name reported by the selected keyboard
+    fn model_name(&self) -> Option<String>;
+
+    /// USB product ID of the selected keyboard, if the backend can read it
+    fn usb_product_id(&self) -> Option<u16>;
+
+    /// Light the keys of the selected keyboard, turning every other key off
+    ///
+    /// The lighting stays until `reset_rgb` is called.
+    fn set_key_colors(&mut self, key_colors: &[KeyColor]) -> bool;
+
+    /// Restore the profile lighting of the selected keyboard
+    fn reset_rgb(&mut self);
+
+    /// A handle restoring the profile lighting of every keyboard and releasing them
+    ///
+    /// The handle doesn't borrow the backend, so it can run while a command is in progress.
+    fn reset_handle(&self) -> ResetHandle;
+}
+
+/* Typings */
+
+/// Restores the profile lighting of every keyboard, see `DeviceBackend::reset_handle`
+pub type ResetHandle = Arc<dyn Fn() + Send + Sync>;
+
+/* Structures */
+
+/// Hardware backend using the Wooting RGB SDK
+#[cfg(feature = "rgb")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RgbBackend;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SimulatedCommand {
+    pub device_index: u8,
+    pub command:      u8,
+    pub parameters:   [u8; 4],
+}
+
+#[derive(Clone, Debug)]
+pub struct SimulatedDevice {
+    pub device:        Device,
+    pub profile_index: u8,
+    pub connected:     bool,
+    /// Number of upcoming profile activations to ignore, emulating a busy keyboard
+    pub busy:          u32,
+    /// Whether the keyboard drops every request for a response, emulating a hung keyboard
+    pub unresponsive:  bool,
+    /// Keys lit with `set_key_colors`, empty while the profile lighting is shown
+    pub key_colors:    Vec<KeyColor>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SimulatedState {
+    pub devices:  Vec<SimulatedDevice>,
+    pub selected: Option<u8>,
+    /// The latest commands sent, oldest first, older commands are dropped
+    pub commands: VecDeque<SimulatedCommand>,
+}
+
+/// In-memory backend emulating any number of keyboards
+///
+/// Clones share the same state, so a clone can be kept to inspect or modify the
+/// keyboards after handing the backend to `set_backend`.
+#[derive(Clone, Debug, Default)]
+pub struct SimulatedBackend {
+    state: Arc<Mutex<SimulatedState>>,
+}
+
+/* Implementations */
+
+#[cfg(feature = "rgb")]
+impl DeviceBackend for RgbBackend {
+    fn find_devices(&mut self) -> bool {
+        unsafe {
+            rgb::wooting_usb_disconnect(false);
+            rgb::wooting_usb_find_keyboard()
+        }
+    }
+
+    fn device_count(&mut self) -> u8 {
+        unsafe {
+            rgb::wooting_usb_disconnect(false);
+            rgb::wooting_usb_find_keyboard();
+            rgb::wooting_usb_device_count()
+        }
+    }
+
+    fn select_device(&mut self, device_index: u8) -> bool {
+        unsafe { rgb::wooting_usb_select_device(device_index) }
+    }
+
+    fn send_feature(&mut self, command: u8, parameters: [u8; 4]) -> bool {
+        let [p0, p1, p2, p3] = parameters;
+        unsafe { rgb::wooting_usb_send_feature(command, p0, p1, p2, p3) }
+    }
+
+    fn send_feature_with_response(
+        &mut self,
+        buffer: &mut [u8],
+        command: u8,
+        parameters: [u8; 4],
+    ) -> Option<usize> {
+        let [p0, p1, p2, p3] = parameters;
+        let response = unsafe {
+            rgb::wooting_usb_send_feature_with_response(
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                command,
+                p0,
+                p1,
+                p2,
+                p3,
+            )
+        };
+
+        usize::try_from(response).ok()
+    }
+
+    fn response_size(&self) -> usize {
+        unsafe { rgb::wooting_usb_get_response_size() as usize }
+    }
+
+    fn uses_multi_report(&self) -> bool {
+        unsafe { rgb::wooting_usb_use_multi_report() }
+    }
+
+    fn uses_v2_interface(&self) -> bool {
+        unsafe { rgb::wooting_usb_use_v2_interface() }
+    }
+
+    fn model_name(&self) -> Option<String> {
+        unsafe {
+            let meta = rgb::wooting_usb_get_meta().as_ref()?;
+            if meta.model.is_null() {
+                return None;
+            }
+
+            let model_name = CStr::from_ptr(meta.model).to_str().ok()?;
+            Some(model_name.replace("Lekker Edition", "LE"))
+        }
+    }
+
+    // The Wooting RGB SDK doesn't expose the product ID
+    fn usb_product_id(&self) -> Option<u16> {
+        None
+    }
+
+    fn set_key_colors(&mut self, key_colors: &[KeyColor]) -> bool {
+        unsafe {
+            rgb::wooting_rgb_array_auto_update(false);
+
+            // The array buffer is shared by every keyboard, clear what another one left behind
+            for row in 0..RGB_ROWS {
+                for column in 0..RGB_COLUMNS {
+                    rgb::wooting_rgb_array_set_single(row, column, 0, 0, 0);
+                }
+            }
+
+            for key_color in key_colors {
+                let color = key_color.color;
+                rgb::wooting_rgb_array_set_single(
+                    key_color.row,
+                    key_color.column,
+                    color.red,
+                    color.green,
+                    color.blue,
+                );
+            }
+
+            rgb::wooting_rgb_array_update_keyboard()
+        }
+    }
+
+    fn reset_rgb(&mut self) {
+        unsafe {
+            rgb::wooting_rgb_reset_rgb();
+        }
+    }
+
+    fn reset_handle(&self) -> ResetHandle {
+        Arc::new(|| unsafe {
+            rgb::wooting_rgb_reset();
+        })
+    }
+}
+
+impl SimulatedDevice {
+    #[must_use]
+    pub fn new(index: u8) -> Self {
+        let model_name = SIMULATED_MODEL_NAMES[usize::from(index) % SIMULATED_MODEL_NAMES.len()];
+        let device = Device {
+            model_name: String::from(model_name),
+            supplier: 2,
+            year: 24,
+            week: 1 + u32::from(index),
+            product: 3,
+            revision: 1,
+            product_id: 1000 + u32::from(index),
+            firmware_version: Some(FirmwareVersion {
+                major: 2,
+                minor: 11,
+                patch: index,
+            }),
+            profile_count: Some(SIMULATED_PROFILE_COUNT),
+            digital_profile_count: Some(1),
+            analog_profile_count: Some(SIMULATED_PROFILE_COUNT - 1),
+            ..Default::default()
+        };
+
+        Self {
+            device,
+            profile_index: 0,
+            connected: true,
+            busy: 0,
+            unresponsive: false,
+            key_colors: Vec::new(),
+        }
+    }
+
+    /// Encode the serial fields as the protobuf payload of a `GetSerial` response
+    fn encode_serial(&self) -> Vec<u8> {
+        let mut fields = vec![
+            (1, u32::from(self.device.supplier)),
+            (2, self.device.year),
+            (3, self.device.week),
+            (4, u32::from(self.device.product)),
+            (5, self.device.revision),
+            (6, self.device.product_id),
+            (7, self.device.stage.clone() as u32),
+        ];
+        fields.extend(self.device.variant.map(|variant| (9, variant)));
+        fields.extend(self.device.pcb_design.map(|pcb_design| (10, pcb_design)));
+        fields.extend(self.device.minor_rev.map(|minor_rev| (11, minor_rev)));
+
+        let mut payload = Vec::new();
+        for (field, mut value) in fields {
+            payload.push(field << 3);
+            while value >= 0x80 {
+                #[allow(clippy::cast_possible_truncation)]
+                payload.push((value as u8 & 0x7F) | 0x80);
+                value >>= 7;
+            }
+            #[allow(clippy::cast_possible_truncation)]
+            payload.push(value as u8);
+        }
+
+        payload
+    }
+}
+
+impl SimulatedState {
+    /// Restore the profile lighting of every keyboard and deselect them
+    fn reset(&mut self) {
+        for device in &mut self.devices {
+            device.key_colors.clear();
+        }
+        self.selected = None;
+    }
+
+    /// Record a command and apply it to the selected keyboard
+    fn handle_feature(&mut self, command: u8, parameters: [u8; 4]) -> bool {
+        let Some(device_index) = self.selected else {
+            return false;
+        };
+
+        if self.commands.len() == SIMULATED_COMMAND_LOG_SIZE {
+            self.commands.pop_front();
+        }
+        self.commands.push_back(SimulatedCommand {
+            device_index,
+            command,
+            parameters,
+        });
+
+        let Some(device) = self.devices.get_mut(usize::from(device_index)) else {
+            return false;
+        };
+
+        if !device.connected {
+            return false;
+        }
+
+        if command == Command::ActivateProfile as u8 && parameters[3] < SIMULATED_PROFILE_COUNT {
+            if device.busy > 0 {
+                device.busy -= 1;
+            } else {
+                device.profile_index = parameters[3];
+            }
+        }
+
+        true
+    }
+}
+
+impl SimulatedBackend {
+    /// Create a backend emulating the given number of keyboards
+    #[must_use]
+    pub fn new(device_count: u8) -> Self {
+        let devices = (0..device_count).map(SimulatedDevice::new).collect();
+        let state = SimulatedState {
+            devices,
+            ..Default::default()
+        };
+
+        Self {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    #[must_use]
+    pub fn state(&self) -> Arc<Mutex<SimulatedState>> {
+        Arc::clone(&self.state)
+    }
+
+    fn selected_device(&self) -> Option<SimulatedDevice> {
+        let state = self.state.lock();
+        let device = state.devices.get(usize::from(state.selected?))?.clone();
+        drop(state);
+
+        device.connected.then_some(device)
+    }
+}
+
+impl DeviceBackend for SimulatedBackend {
+    fn find_devices(&mut self) -> bool {
+        let mut state = self.state.lock();
+        state.selected = None;
+        state.devices.iter().any(|device| device.connected)
+    }
+
+    fn device_count(&mut self) -> u8 {
+        let count = self
+            .state
+            .lock()
+            .devices
+            .iter()
+            .filter(|device| device.connected)
+            .count();
+
+        u8::try_from(count).unwrap_or(u8::MAX)
+    }
+
+    fn select_device(&mut self, device_index: u8) -> bool {
+        let mut state = self.state.lock();
+        let connected = state
+            .devices
+            .get(usize::from(device_index))
+            .is_some_and(|device| device.connected);
+
+        if connected {
+            state.selected = Some(device_index);
+        }
+
+        connected
+    }
+
+    fn send_feature(&mut self, command: u8, parameters: [u8; 4]) -> bool {
+        self.state.lock().handle_feature(command, parameters)
+    }
+
+    fn send_feature_with_response(
+        &mut self,
+        buffer: &mut [u8],
+        command: u8,
+        parameters: [u8; 4],
+    ) -> Option<usize> {
+        if !self.send_feature(command, parameters) {
+            return None;
+        }
+
+        let device = self
+            .selected_device()
+            .filter(|device| !device.unresponsive)?;
+        let payload = match Command::from_repr(command) {
+            Some(Command::GetVersion) => device
+                .device
+                .firmware_version
+                .map(|version| vec![version.major, version.minor, version.patch])
+                .unwrap_or_default(),
+            Some(Command::GetSerial) => device.encode_serial(),
+            Some(Command::GetRgbProfileCount) => device.device.profile_count.into_iter().collect(),
+            Some(Command::GetDigitalProfilesCount) => {
+                device.device.digital_profile_count.into_iter().collect()
+            }
+            Some(Command::GetAnalogProfilesCount) => {
+                device.device.analog_profile_count.into_iter().collect()
+            }
+            Some(Command::GetCurrentKeyboardProfileIndex) => vec![device.profile_index],
+            _ => Vec::new(),
+        };
+
+        let frame = Frame {
+            command,
+            payload: &payload,
+        };
+        let response = frame.encode(FrameLayout::STANDARD, SIMULATED_RESPONSE_SIZE);
+
+        let length = buffer.len().min(response.len());
+        buffer[..length].copy_from_slice(&response[..length]);
+
+        Some(length)
+    }
+
+    fn response_size(&self) -> usize {
+        SIMULATED_RESPONSE_SIZE
+    }
+
+    fn uses_multi_report(&self) -> bool {
+        false
+    }
+
+    fn uses_v2_interface(&self) -> bool {
+        true
+    }
+
+    fn model_name(&self) -> Option<String> {
+        self.selected_device()
+            .map(|device| device.device.model_name)
+    }
+
+    fn usb_product_id(&self) -> Option<u16> {
+        self.selected_device()?.device.usb_product_id
+    }
+
+    fn set_key_colors(&mut self, key_colors: &[KeyColor]) -> bool {
+        let mut state = self.state.lock();
+        let Some(device_index) = state.selected else {
+            return false;
+        };
+
+        match state.devices.get_mut(usize::from(device_index)) {
+            Some(device) if device.connected => {
+                device.key_colors = key_colors.to_vec();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn reset_rgb(&mut self) {
+        let mut state = self.state.lock();
+        if let Some(device_index) = state.selected {
+            if let Some(device) = state.devices.get_mut(usize::from(device_index)) {
+                device.key_colors.clear();
+            }
+        }
+    }
+
+    fn reset_handle(&self) -> ResetHandle {
+        let state = self.state();
+        Arc::new(move || state.lock().reset())
+    }
+}
@@ -4,28 +4,118 @@ use std::{
     fs::File,
     io::{Read, Write},
     path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
 };
 
 use anyhow::{bail, Result};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use wooting_profile_switcher as wps;
-use wps::{Device, DeviceIndices, DeviceSerial};
+use wps::{
+    Device,
+    DeviceIndices,
+    DeviceSerial,
+    FlashOptions,
+    LightingMode,
+    Model,
+    RgbColor,
+    SwitchOptions,
+    WatchOptions,
+    PROFILE_KEY,
+};
+
+use crate::{rules::MatchPattern, theme::Theme};
 
-use crate::theme::Theme;
+/// Bumped whenever the config is loaded or saved, so state derived from it can be rebuilt
+static REVISION: AtomicU64 = AtomicU64::new(0);
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 #[serde(default)]
 pub struct Rule {
-    pub alias:          String,
-    pub device_indices: DeviceIndices,
-    #[serde(alias = "app_name")]
+    pub alias:           String,
+    pub device_indices:  DeviceIndices,
+    #[serde(alias = "app_name", deserialize_with = "deserialize_pattern")]
+    pub match_app_name:  Option<String>,
+    #[serde(alias = "process_name", deserialize_with = "deserialize_pattern")]
+    pub match_bin_name:  Option<String>,
+    #[serde(alias = "process_path", deserialize_with = "deserialize_pattern")]
+    pub match_bin_path:  Option<String>,
+    #[serde(alias = "title", deserialize_with = "deserialize_pattern")]
+    pub match_win_name:  Option<String>,
+    /// Whether any or all of the match fields and groups must match
+    pub match_mode:      MatchMode,
+    /// Nested conditions, each counting as one more match field
+    pub match_groups:    Vec<MatchGroup>,
+    /// Breaks ties between equally specific rules, higher wins, only for the most specific strategy
+    pub priority:        i32,
+    /// Overrides how long a window must keep focus before the rule is applied
+    pub switch_delay_ms: Option<u64>,
+    /// What a switch changes on the devices, defaults to the global lighting mode
+    pub lighting_mode:   Option<LightingMode>,
+    /// What a switch changes on each device, defaults to the rule lighting mode
+    pub lighting_modes:  HashMap<DeviceSerial, LightingMode>,
+    /// Key name to color map lit on every keyboard while the rule matches
+    pub overlay:         HashMap<String, RgbColor>,
+}
+
+/// How the match fields and groups of a rule or group combine
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchMode {
+    #[default]
+    Any,
+    All,
+}
+
+/// How the rule for a device is picked among the matching rules
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchStrategy {
+    /// The first matching rule in the list
+    #[default]
+    FirstMatch,
+    /// The matching rule with the most exact fields, then the most non-wildcard fields, then
+    /// the highest priority, then the first in the list
+    MostSpecific,
+}
+
+impl MatchStrategy {
+    pub const ALL: [Self; 2] = [Self::FirstMatch, Self::MostSpecific];
+
+    pub const fn display_name(self) -> &'static str {
+        match self {
+            Self::FirstMatch => "First match",
+            Self::MostSpecific => "Most specific",
+        }
+    }
+}
+
+/// A nested group of match conditions, optionally negated
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default)]
+pub struct MatchGroup {
+    #[serde(deserialize_with = "deserialize_pattern")]
     pub match_app_name: Option<String>,
-    #[serde(alias = "process_name")]
+    #[serde(deserialize_with = "deserialize_pattern")]
     pub match_bin_name: Option<String>,
-    #[serde(alias = "process_path")]
+    #[serde(deserialize_with = "deserialize_pattern")]
     pub match_bin_path: Option<String>,
-    #[serde(alias = "title")]
+    #[serde(deserialize_with = "deserialize_pattern")]
     pub match_win_name: Option<String>,
+    pub match_mode: MatchMode,
+    pub match_groups: Vec<Self>,
+    /// Inverts the result of the group
+    pub not: bool,
+}
+
+impl Rule {
+    // The lighting mode of a device the rule switches, falling back to the global one
+    pub fn lighting_mode(&self, device_serial: &DeviceSerial) -> Option<LightingMode> {
+        self.lighting_modes
+            .get(device_serial)
+            .copied()
+            .or(self.lighting_mode)
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -52,10 +142,22 @@ pub struct Config {
     pub auto_launch: Option<bool>,
     pub auto_update: Option<bool>,
     pub devices: HashMap<DeviceSerial, Device>,
+    pub flash: HashMap<DeviceSerial, FlashOptions>,
+    pub hotplug_sleep_ms: u64,
+    #[serde(
+        alias = "swap_lighting",
+        deserialize_with = "deserialize_lighting_mode"
+    )]
+    pub lighting_mode: LightingMode,
     pub loop_sleep_ms: u64,
+    pub match_strategy: MatchStrategy,
+    pub models: Vec<Model>,
+    pub profile_sleep_ms: u64,
     pub send_sleep_ms: u64,
     pub show_serial: bool,
-    pub swap_lighting: bool,
+    /// How long a window must keep focus before its rule is applied, 0 applies it right away
+    pub switch_delay_ms: u64,
+    pub switch_retries: u32,
     pub rules: Vec<Rule>,
     pub ui: Ui,
 }
@@ -66,26 +168,47 @@ impl Default for Config {
             auto_launch: None,
             auto_update: None,
             devices: HashMap::new(),
+            flash: HashMap::new(),
+            hotplug_sleep_ms: 1000,
+            lighting_mode: LightingMode::Both,
             loop_sleep_ms: 250,
+            match_strategy: MatchStrategy::FirstMatch,
+            models: Vec::new(),
+            profile_sleep_ms: 250,
             send_sleep_ms: 250,
             show_serial: false,
-            swap_lighting: true,
+            switch_delay_ms: 0,
+            switch_retries: 3,
             rules: vec![
                 Rule {
                     alias: String::from("The Binding of Isaac"),
                     device_indices: DeviceIndices::new(),
                     match_app_name: None,
                     match_bin_name: None,
-                    match_bin_path: Some(String::from("C:\\Program Files (x86)\\Steam\\steamapps\\common\\The Binding of Isaac Rebirth*")),
+                    match_bin_path: Some(String::from("glob:C:\\Program Files (x86)\\Steam\\steamapps\\common\\The Binding of Isaac Rebirth*")),
                     match_win_name: None,
+                    match_mode: MatchMode::Any,
+                    match_groups: Vec::new(),
+                    priority: 0,
+                    switch_delay_ms: None,
+                    lighting_mode: None,
+                    lighting_modes: HashMap::new(),
+                    overlay: HashMap::new(),
                 },
                 Rule {
                     alias: String::from("Default Fallback"),
-                    device_indices: wps::get_device_indices().unwrap_or_default(),
+                    device_indices: DeviceIndices::new(),
                     match_app_name: Some(String::from("*")),
                     match_bin_name: Some(String::from("*")),
                     match_bin_path: Some(String::from("*")),
                     match_win_name: Some(String::from("*")),
+                    match_mode: MatchMode::Any,
+                    match_groups: Vec::new(),
+                    priority: 0,
+                    switch_delay_ms: None,
+                    lighting_mode: None,
+                    lighting_modes: HashMap::new(),
+                    overlay: HashMap::new(),
                 }
             ],
             ui: Ui::default(),
@@ -94,6 +217,23 @@ impl Default for Config {
 }
 
 impl Config {
+    pub fn switch_options(&self) -> SwitchOptions {
+        SwitchOptions {
+            send_timeout_ms: self.send_sleep_ms,
+            lighting:        self.lighting_mode,
+            lighting_modes:  HashMap::new(),
+            retries:         self.switch_retries,
+            flash:           self.flash.clone(),
+        }
+    }
+
+    pub const fn watch_options(&self) -> WatchOptions {
+        WatchOptions {
+            hotplug_interval: Duration::from_millis(self.hotplug_sleep_ms),
+            profile_interval: Duration::from_millis(self.profile_sleep_ms),
+        }
+    }
+
     pub fn get_path() -> Result<PathBuf> {
         let mut path = std::env::current_exe()?;
         path.set_extension("json");
@@ -121,11 +261,14 @@ impl Config {
             let mut text = String::new();
             file.read_to_string(&mut text)?;
 
-            serde_json::from_str(&text).unwrap_or_else(|error| {
+            let config: Self = serde_json::from_str(&text).unwrap_or_else(|error| {
                 eprintln!("There was an error parsing the config: {error}");
                 eprintln!("Temporarily using the default config");
-                Self::default()
-            })
+                Self::initial()
+            });
+            config.check_serials();
+            config.check_keys();
+            config
         } else {
             if path.exists() {
                 // Rename the existing config file
@@ -136,12 +279,88 @@ impl Config {
 
             // Create a new config file and write default config
             let mut file = File::create(&path)?;
-            let config = Self::default();
+            let config = Self::initial();
             let text = serde_json::to_string_pretty(&config)?;
             file.write_all(text.as_bytes())?;
             config
         };
 
+        REVISION.fetch_add(1, Ordering::Relaxed);
+
+        Ok(config)
+    }
+
+    // Parses the config file as is, without creating it, falling back or touching the keyboards
+    pub fn parse() -> Result<Self> {
+        let path = Self::get_path()?;
+        let Ok(text) = std::fs::read_to_string(&path) else {
+            bail!("Could not read the config file: {}", path.display());
+        };
+
+        match serde_json::from_str(&text) {
+            Ok(config) => Ok(config),
+            Err(error) => bail!("There was an error parsing the config: {error}"),
+        }
+    }
+
+    // The default config, its fallback rule keeping the active profile of each connected keyboard
+    fn initial() -> Self {
+        let mut config = Self::default();
+        if let Some(rule) = config.rules.last_mut() {
+            rule.device_indices = wps::get_device_indices().unwrap_or_default();
+        }
+
+        config
+    }
+
+    // Changes whenever a config is loaded or saved
+    pub fn revision() -> u64 {
+        REVISION.load(Ordering::Relaxed)
+    }
+
+    // Warns about malformed serials, they can never match a connected keyboard
+    fn check_serials(&self) {
+        let device_serials = self
+            .devices
+            .keys()
+            .map(|device_serial| (String::from("devices"), device_serial));
+        let rule_serials = self.rules.iter().flat_map(|rule| {
+            rule.device_indices
+                .keys()
+                .map(move |device_serial| (format!("rule \"{}\"", rule.alias), device_serial))
+        });
+
+        for (location, device_serial) in device_serials.chain(rule_serials) {
+            if let Err(error) = device_serial.fields() {
+                eprintln!("Invalid device serial {device_serial} in {location}: {error}");
+            }
+        }
+    }
+
+    // Warns about key names missing from the lighting matrix, they are skipped when lit
+    fn check_keys(&self) {
+        for (device_serial, flash) in &self.flash {
+            for key in &flash.keys {
+                if !key.eq_ignore_ascii_case(PROFILE_KEY) && wps::key_position(key).is_none() {
+                    eprintln!("Unknown key {key} in flash for {device_serial}");
+                }
+            }
+        }
+
+        for rule in &self.rules {
+            for key in rule.overlay.keys() {
+                if wps::key_position(key).is_none() {
+                    eprintln!("Unknown key {key} in overlay for rule \"{}\"", rule.alias);
+                }
+            }
+        }
+    }
+
+    // Loads the config and hands its model overrides to the library
+    pub fn load_and_apply() -> Result<Self> {
+        let config = Self::load()?;
+        wps::set_models(config.models.clone());
+
         Ok(config)
     }
 
@@ -155,7 +374,78 @@ impl Config {
 
         let content = serde_json::to_string_pretty(&self)?;
         file.write_all(content.as_bytes())?;
+        REVISION.fetch_add(1, Ordering::Relaxed);
 
         Ok(())
     }
 }
+
+// Accepts a pattern in the prefix syntax or as an object, stored in the prefix syntax
+fn deserialize_pattern<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Pattern {
+        Prefixed(String),
+        Structured(MatchPattern),
+    }
+
+    Ok(
+        Option::<Pattern>::deserialize(deserializer)?.map(|pattern| match pattern {
+            Pattern::Prefixed(pattern) => pattern,
+            Pattern::Structured(pattern) => pattern.to_string(),
+        }),
+    )
+}
+
+// Accepts the swap_lighting boolean of older configs
+fn deserialize_lighting_mode<'de, D>(deserializer: D) -> Result<LightingMode, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Legacy {
+        SwapLighting(bool),
+        LightingMode(LightingMode),
+    }
+
+    Ok(match Legacy::deserialize(deserializer)? {
+        Legacy::SwapLighting(true) => LightingMode::Both,
+        Legacy::SwapLighting(false) => LightingMode::ProfileOnly,
+        Legacy::LightingMode(lighting_mode) => lighting_mode,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lighting_mode(json: &str) -> LightingMode {
+        serde_json::from_str::<Config>(json).unwrap().lighting_mode
+    }
+
+    #[test]
+    fn reads_the_legacy_swap_lighting() {
+        assert_eq!(
+            lighting_mode(r#"{"swap_lighting": true}"#),
+            LightingMode::Both
+        );
+        assert_eq!(
+            lighting_mode(r#"{"swap_lighting": false}"#),
+            LightingMode::ProfileOnly
+        );
+    }
+
+    #[test]
+    fn reads_the_lighting_mode() {
+        assert_eq!(lighting_mode("{}"), LightingMode::Both);
+        assert_eq!(
+            lighting_mode(r#"{"lighting_mode": "lighting_only"}"#),
+            LightingMode::LightingOnly
+        );
+        assert!(serde_json::from_str::<Config>(r#"{"lighting_mode": "swap"}"#).is_err());
+    }
+}
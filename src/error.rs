@@ -0,0 +1,49 @@
+use derive_more::{Display, Error, From};
+
+use crate::{Command, DecodeError, ProfileIndex};
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug, Display, Error, From)]
+pub enum Error {
+    #[display("Failed to find keyboard(s)")]
+    NoDevices,
+    #[display("Device ({device}) not found")]
+    DeviceNotFound { device: String },
+    #[display("Device did not respond to command {command}")]
+    Timeout { command: Command },
+    #[display("Failed to send command {command}")]
+    SendFailed { command: Command },
+    #[display("Invalid response length: got {got}, expected {expected}")]
+    InvalidResponse { expected: usize, got: usize },
+    #[display("Invalid response magic word: got {got:#06X}, expected {expected:#06X}")]
+    ProtocolMismatch { expected: u16, got: u16 },
+    #[display("Invalid response command: got {got}, expected {expected}")]
+    UnexpectedCommand { expected: Command, got: u8 },
+    #[display("Invalid profile index {profile_index}")]
+    InvalidProfileIndex { profile_index: ProfileIndex },
+    #[display(
+        "Profile index {profile_index} out of range, the keyboard has {profile_count} profile(s)"
+    )]
+    ProfileOutOfRange {
+        profile_index: ProfileIndex,
+        profile_count: u8,
+    },
+    #[display(
+        "Profile switch failed after {attempts} attempt(s): got {got:?}, expected {expected}"
+    )]
+    SwitchFailed {
+        expected: ProfileIndex,
+        got:      Option<ProfileIndex>,
+        attempts: u32,
+    },
+    #[display("Device worker stopped")]
+    WorkerStopped,
+    #[display("Invalid model name")]
+    InvalidModelName,
+    #[display("Unknown model ({model_name})")]
+    UnknownModel { model_name: String },
+    #[display("Invalid serial buffer: {_0}")]
+    #[from]
+    Decode(DecodeError),
+}
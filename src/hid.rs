@@ -0,0 +1,318 @@
+use std::{sync::Arc, time::Duration};
+
+use hidapi::{DeviceInfo, HidApi, HidDevice};
+
+use crate::{Command, DeviceBackend, KeyColor, ResetHandle, MAGIC_WORD_V2, MAGIC_WORD_V3};
+
+/* Constants */
+
+// https://github.com/WootingKb/wooting-rgb-sdk/blob/master/src/usb.c
+const WOOTING_VID: u16 = 0x31E3;
+const WOOTING_LEGACY_VID: u16 = 0x03EB;
+const WOOTING_USAGE_PAGE: u16 = 0x1337;
+const COMMAND_SIZE: usize = 8;
+const MAX_RESPONSE_SIZE: usize = 1024;
+const READ_TIMEOUT: Duration = Duration::from_secs(1);
+
+/* Structures */
+
+/// How the responses of a keyboard are laid out
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Layout {
+    response_size: usize,
+    multi_report:  bool,
+    v2_interface:  bool,
+}
+
+/// An opened keyboard and the layout of its responses
+struct Keyboard {
+    device:     HidDevice,
+    layout:     Layout,
+    model_name: Option<String>,
+    product_id: u16,
+}
+
+/// Backend talking to the Wooting feature report interface directly through hidraw
+///
+/// This keeps the whole protocol in Rust, without linking the Wooting RGB SDK. The
+/// keyboards' lighting is never taken over, so there is nothing to reset.
+#[derive(Default)]
+pub struct HidBackend {
+    api:       Option<HidApi>,
+    devices:   Vec<DeviceInfo>,
+    keyboards: Vec<Option<Keyboard>>,
+    selected:  Option<usize>,
+}
+
+/* Implementations */
+
+impl Keyboard {
+    /// Open the keyboard and detect its layout from the response to a harmless command
+    fn open(api: &HidApi, info: &DeviceInfo) -> Option<Self> {
+        let device = info.open_device(api).ok()?;
+        let v2_interface = info.vendor_id() == WOOTING_VID;
+        let mut keyboard = Self {
+            device,
+            layout: Layout {
+                response_size: MAX_RESPONSE_SIZE,
+                multi_report: false,
+                v2_interface,
+            },
+            model_name: info
+                .product_string()
+                .map(|model_name| model_name.replace("Lekker Edition", "LE")),
+            product_id: info.product_id(),
+        };
+
+        let mut buffer = [0u8; MAX_RESPONSE_SIZE];
+        let command = Command::GetCurrentKeyboardProfileIndex as u8;
+        let length = keyboard.request(&mut buffer, command, [0; 4])?;
+        keyboard.layout = detect_layout(&buffer[..length], v2_interface)?;
+
+        Some(keyboard)
+    }
+
+    fn send(&self, command: u8, parameters: [u8; 4]) -> bool {
+        // Discard responses left over from commands sent without waiting for a response
+        let mut buffer = [0u8; MAX_RESPONSE_SIZE];
+        while self
+            .device
+            .read_timeout(&mut buffer, 0)
+            .is_ok_and(|length| length > 0)
+        {}
+
+        self.device
+            .send_feature_report(&encode_feature(command, parameters))
+            .is_ok()
+    }
+
+    fn request(&self, buffer: &mut [u8], command: u8, parameters: [u8; 4]) -> Option<usize> {
+        if !self.send(command, parameters) {
+            return None;
+        }
+
+        #[allow(clippy::cast_possible_truncation)] // One second
+        let timeout = READ_TIMEOUT.as_millis() as i32;
+        let length = self.device.read_timeout(buffer, timeout).ok()?;
+
+        (length > 0).then_some(length)
+    }
+}
+
+impl HidBackend {
+    fn keyboard(&self) -> Option<&Keyboard> {
+        self.keyboards.get(self.selected?)?.as_ref()
+    }
+}
+
+impl DeviceBackend for HidBackend {
+    fn find_devices(&mut self) -> bool {
+        self.keyboards.clear();
+        self.selected = None;
+
+        if self.api.is_none() {
+            self.api = HidApi::new().ok();
+        }
+
+        let Some(api) = self.api.as_mut() else {
+            return false;
+        };
+
+        self.devices = enumerate(api);
+        self.keyboards = self.devices.iter().map(|_| None).collect();
+
+        !self.devices.is_empty()
+    }
+
+    // Leaves the opened keyboards alone, they stay usable if nothing changed
+    fn device_count(&mut self) -> u8 {
+        if self.api.is_none() {
+            self.api = HidApi::new().ok();
+        }
+
+        let count = self.api.as_mut().map_or(0, |api| enumerate(api).len());
+        u8::try_from(count).unwrap_or(u8::MAX)
+    }
+
+    fn select_device(&mut self, device_index: u8) -> bool {
+        let index = usize::from(device_index);
+        let (Some(api), Some(info)) = (self.api.as_ref(), self.devices.get(index)) else {
+            return false;
+        };
+
+        if self.keyboards[index].is_none() {
+            self.keyboards[index] = Keyboard::open(api, info);
+        }
+
+        let opened = self.keyboards[index].is_some();
+        if opened {
+            self.selected = Some(index);
+        }
+
+        opened
+    }
+
+    fn send_feature(&mut self, command: u8, parameters: [u8; 4]) -> bool {
+        self.keyboard()
+            .is_some_and(|keyboard| keyboard.send(command, parameters))
+    }
+
+    fn send_feature_with_response(
+        &mut self,
+        buffer: &mut [u8],
+        command: u8,
+        parameters: [u8; 4],
+    ) -> Option<usize> {
+        self.keyboard()?.request(buffer, command, parameters)
+    }
+
+    fn response_size(&self) -> usize {
+        self.keyboard()
+            .map_or(0, |keyboard| keyboard.layout.response_size)
+    }
+
+    fn uses_multi_report(&self) -> bool {
+        self.keyboard()
+            .is_some_and(|keyboard| keyboard.layout.multi_report)
+    }
+
+    fn uses_v2_interface(&self) -> bool {
+        self.keyboard()
+            .is_some_and(|keyboard| keyboard.layout.v2_interface)
+    }
+
+    fn model_name(&self) -> Option<String> {
+        self.keyboard()?.model_name.clone()
+    }
+
+    fn usb_product_id(&self) -> Option<u16> {
+        Some(self.keyboard()?.product_id)
+    }
+
+    // Direct lighting isn't implemented for hidraw yet
+    fn set_key_colors(&mut self, _key_colors: &[KeyColor]) -> bool {
+        false
+    }
+
+    fn reset_rgb(&mut self) {}
+
+    fn reset_handle(&self) -> ResetHandle {
+        // The handles are closed when the process exits
+        Arc::new(|| {})
+    }
+}
+
+/* Helpers */
+
+/// Refresh the device list and return the Wooting feature report interfaces
+fn enumerate(api: &mut HidApi) -> Vec<DeviceInfo> {
+    let _ = api.reset_devices();
+    let _ = api.add_devices(WOOTING_VID, 0);
+    let _ = api.add_devices(WOOTING_LEGACY_VID, 0);
+
+    api.device_list()
+        .filter(|info| info.usage_page() == WOOTING_USAGE_PAGE)
+        .cloned()
+        .collect()
+}
+
+/// Encode a command as a feature report
+///
+/// The parameters are sent in reverse order, matching the Wooting RGB SDK.
+const fn encode_feature(command: u8, parameters: [u8; 4]) -> [u8; COMMAND_SIZE] {
+    let [p0, p1, p2, p3] = parameters;
+    let [magic_low, magic_high] = MAGIC_WORD_V2.to_le_bytes();
+
+    [0, magic_low, magic_high, command, p3, p2, p1, p0]
+}
+
+/// Detect the layout of a keyboard from a full response report
+///
+/// Multi-report keyboards prefix responses with a report ID and use the V3 magic word.
+fn detect_layout(response: &[u8], v2_interface: bool) -> Option<Layout> {
+    let magic_word_at = |offset: usize| {
+        let bytes = response.get(offset..offset + 2)?;
+        Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+    };
+
+    let multi_report = if magic_word_at(0) == Some(MAGIC_WORD_V2) {
+        false
+    } else if magic_word_at(1) == Some(MAGIC_WORD_V3) {
+        true
+    } else {
+        return None;
+    };
+
+    Some(Layout {
+        response_size: response.len(),
+        multi_report,
+        v2_interface,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ACTIVATE_PROFILE: u8 = Command::ActivateProfile as u8;
+    const GET_CURRENT_KEYBOARD_PROFILE_INDEX: u8 = Command::GetCurrentKeyboardProfileIndex as u8;
+
+    #[test]
+    fn encodes_feature_reports() {
+        assert_eq!(
+            encode_feature(ACTIVATE_PROFILE, [0, 0, 0, 2]),
+            [0x00, 0xD0, 0xDA, ACTIVATE_PROFILE, 0x02, 0x00, 0x00, 0x00]
+        );
+        assert_eq!(
+            encode_feature(GET_CURRENT_KEYBOARD_PROFILE_INDEX, [1, 2, 3, 4]),
+            [
+                0x00,
+                0xD0,
+                0xDA,
+                GET_CURRENT_KEYBOARD_PROFILE_INDEX,
+                4,
+                3,
+                2,
+                1
+            ]
+        );
+    }
+
+    #[test]
+    fn detects_standard_reports() {
+        let mut response = vec![0xD0, 0xDA, GET_CURRENT_KEYBOARD_PROFILE_INDEX, 0, 1, 2];
+        response.resize(256, 0);
+
+        assert_eq!(
+            detect_layout(&response, true),
+            Some(Layout {
+                response_size: 256,
+                multi_report:  false,
+                v2_interface:  true,
+            })
+        );
+    }
+
+    #[test]
+    fn detects_multi_reports() {
+        let mut response = vec![0x01, 0xD1, 0xDA, GET_CURRENT_KEYBOARD_PROFILE_INDEX, 0, 1];
+        response.resize(257, 0);
+
+        assert_eq!(
+            detect_layout(&response, true),
+            Some(Layout {
+                response_size: 257,
+                multi_report:  true,
+                v2_interface:  true,
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_reports() {
+        assert_eq!(detect_layout(&[0x01, 0xD0, 0xDA, 0x0B], true), None);
+        assert_eq!(detect_layout(&[0xD1, 0xDA, 0x0B], false), None);
+        assert_eq!(detect_layout(&[0xD0], false), None);
+        assert_eq!(detect_layout(&[], false), None);
+    }
+}
@@ -1,26 +1,104 @@
-use std::{collections::HashMap, ffi::CStr, time::Duration};
-
-use anyhow::{bail, Error, Result};
+use std::{
+    collections::HashMap,
+    sync::LazyLock,
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "rgb")]
+pub use backend::RgbBackend;
+pub use backend::{
+    DeviceBackend,
+    ResetHandle,
+    SimulatedBackend,
+    SimulatedCommand,
+    SimulatedDevice,
+    SimulatedState,
+    SIMULATED_PROFILE_COUNT,
+};
 use derive_more::{Display, FromStr};
+pub use error::{Error, Result};
+#[cfg(feature = "hid")]
+pub use hid::HidBackend;
+pub use lighting::{
+    key_colors,
+    key_names,
+    key_position,
+    ColorError,
+    FlashOptions,
+    KeyColor,
+    RgbColor,
+    PROFILE_KEY,
+    RGB_COLUMNS,
+    RGB_ROWS,
+};
+pub use manager::DeviceManager;
+pub use models::{default_models, keyboard_type, set_models, Model};
+use parking_lot::Mutex;
+pub use protobuf::DecodeError;
+use protobuf::Reader;
+pub use protocol::{
+    query,
+    send,
+    ActivateProfile,
+    Command,
+    Frame,
+    FrameLayout,
+    GetAnalogProfilesCount,
+    GetCurrentKeyboardProfileIndex,
+    GetDigitalProfilesCount,
+    GetRgbProfileCount,
+    GetSerial,
+    GetVersion,
+    Query,
+    RefreshRgbColors,
+    ReloadProfile,
+    Request,
+    WootDevResetAll,
+    MAGIC_WORD_V2,
+    MAGIC_WORD_V3,
+};
 use serde::{Deserialize, Serialize};
+pub use serial::{SerialError, SerialFields};
 use strum::FromRepr;
+pub use watcher::{watch, DeviceEvent, WatchOptions};
+#[cfg(feature = "rgb")]
 use wooting_rgb_sys as rgb;
+pub use worker::{run, submit, Pending};
+
+mod backend;
+mod error;
+#[cfg(feature = "hid")]
+mod hid;
+mod lighting;
+mod manager;
+mod models;
+mod protobuf;
+mod protocol;
+mod serial;
+mod watcher;
+mod worker;
 
 /* Constants */
 
-// Reverse Engineered from Wootility
-// https://gist.github.com/BigBrainAFK/0ba454a1efb43f7cb6301cda8838f432
-const MAGIC_WORD_V2: u16 = 0xDAD0;
-const MAGIC_WORD_V3: u16 = 0xDAD1;
-const GET_SERIAL: u8 = 3;
-const RELOAD_PROFILE: u8 = 7;
-const GET_CURRENT_KEYBOARD_PROFILE_INDEX: u8 = 11;
-const ACTIVATE_PROFILE: u8 = 23;
-const REFRESH_RGB_COLORS: u8 = 29;
-const WOOT_DEV_RESET_ALL: u8 = 32;
+const ACK_POLL_INTERVAL: Duration = Duration::from_millis(10);
+const RETRY_BACKOFF: Duration = Duration::from_millis(50);
+const MAX_RETRY_BACKOFF: Duration = Duration::from_millis(800);
+const MAX_RETRIES: u32 = 5;
 
+#[cfg(feature = "rgb")]
 #[allow(clippy::cast_possible_truncation)] // Max is 10
 const WOOTING_RGB_MAX_DEVICES: u8 = rgb::WOOTING_MAX_RGB_DEVICES as u8;
+#[cfg(not(feature = "rgb"))]
+const WOOTING_RGB_MAX_DEVICES: u8 = 10;
+
+static MANAGER: LazyLock<Mutex<DeviceManager>> = LazyLock::new(|| {
+    let backend = default_backend();
+    *RESET_HANDLE.lock() = Some(backend.reset_handle());
+    Mutex::new(DeviceManager::new(backend))
+});
+
+/// Kept outside the manager so a reset doesn't wait for the command in progress
+static RESET_HANDLE: Mutex<Option<ResetHandle>> = Mutex::new(None);
 
 /* Typings */
 
@@ -45,16 +123,25 @@ pub struct U32(u32);
 #[derive(Clone, Debug, Default, Display, Deserialize, Eq, FromStr, Hash, PartialEq, Serialize)]
 pub struct DeviceID(String);
 
-#[derive(Clone, Debug, Default, Display, Deserialize, Eq, FromStr, Hash, PartialEq, Serialize)]
+/// A device serial, see `SerialFields` for the layout
+#[derive(Clone, Debug, Default, Display, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub struct DeviceSerial(String);
 
+#[derive(Clone, Copy, Debug, Deserialize, Display, Eq, Hash, PartialEq, Serialize)]
+#[display("{major}.{minor}.{patch}")]
+pub struct FirmwareVersion {
+    pub major: u8,
+    pub minor: u8,
+    pub patch: u8,
+}
+
 #[derive(Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub struct Device {
     pub model_name: String,
-    pub supplier:   u32,
+    pub supplier:   u8,
     pub year:       u32,
     pub week:       u32,
-    pub product:    u32,
+    pub product:    u8,
     pub revision:   u32,
     pub product_id: u32,
     pub stage:      Stage,
@@ -62,33 +149,99 @@ pub struct Device {
     pub pcb_design: Option<u32>,
     pub minor_rev:  Option<u32>,
     pub profiles:   Vec<String>,
+
+    /// USB product ID, only reported by the hid backend
+    pub usb_product_id: Option<u16>,
+
+    /// Missing if the firmware doesn't support the command
+    pub firmware_version:      Option<FirmwareVersion>,
+    /// Number of profile slots, including the digital profile
+    pub profile_count:         Option<u8>,
+    pub digital_profile_count: Option<u8>,
+    pub analog_profile_count:  Option<u8>,
+}
+
+/// What a switch to a profile slot changes on the keyboard
+#[derive(Clone, Copy, Debug, Default, Deserialize, Display, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LightingMode {
+    /// Activate the profile and swap the lighting effects with it
+    #[default]
+    #[display("Profile and lighting")]
+    Both,
+    /// Activate the profile and keep the current lighting effects
+    #[display("Profile only")]
+    ProfileOnly,
+    /// Show the lighting effects of the profile slot and keep the active profile
+    #[display("Lighting only")]
+    LightingOnly,
+}
+
+/// Options controlling how a profile switch is sent and verified
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SwitchOptions {
+    /// Maximum duration to wait for the keyboard to acknowledge a command
+    pub send_timeout_ms: u64,
+    /// What a switch changes on devices without their own lighting mode
+    pub lighting:        LightingMode,
+    /// What a switch changes on each device
+    pub lighting_modes:  HashMap<DeviceSerial, LightingMode>,
+    /// Number of times to retry a switch that didn't take effect, at most 5
+    pub retries:         u32,
+    /// Keys to light on each device after a switch
+    pub flash:           HashMap<DeviceSerial, FlashOptions>,
 }
 
 /* Implementations */
 
-/// Reverse engineered from Wootility
-impl TryFrom<Vec<u8>> for U32 {
-    type Error = Error;
+impl Default for SwitchOptions {
+    fn default() -> Self {
+        Self {
+            send_timeout_ms: 250,
+            lighting:        LightingMode::Both,
+            lighting_modes:  HashMap::new(),
+            retries:         3,
+            flash:           HashMap::new(),
+        }
+    }
+}
 
-    fn try_from(bytes: Vec<u8>) -> Result<Self, Self::Error> {
-        let mut result: u32 = 0;
-        let mut shift: u32 = 0;
+impl SwitchOptions {
+    /// What a switch changes on the device
+    #[must_use]
+    pub fn lighting_mode(&self, device_serial: &DeviceSerial) -> LightingMode {
+        self.lighting_modes
+            .get(device_serial)
+            .copied()
+            .unwrap_or(self.lighting)
+    }
+}
 
-        for byte in &bytes {
-            result |= u32::from(byte & 0x7F) << shift;
-            shift += 7;
+impl LightingMode {
+    /// Whether the switch activates the profile
+    #[must_use]
+    pub const fn switches_profile(self) -> bool {
+        matches!(self, Self::Both | Self::ProfileOnly)
+    }
 
-            if byte & 0x80 == 0 {
-                return Ok(Self(result));
-            }
+    /// Whether the switch swaps the lighting effects
+    #[must_use]
+    pub const fn swaps_lighting(self) -> bool {
+        matches!(self, Self::Both | Self::LightingOnly)
+    }
+}
 
-            if shift > 28 {
-                bail!("Integer too large");
-            }
-        }
+/// Reverse engineered from Wootility
+impl TryFrom<Vec<u8>> for U32 {
+    type Error = DecodeError;
 
-        println!("Incomplete integer");
-        Ok(Self(result))
+    fn try_from(bytes: Vec<u8>) -> Result<Self, Self::Error> {
+        let key = protobuf::Key {
+            field:     0,
+            wire_type: 0,
+        };
+
+        Reader::new(&bytes).read_u32(key).map(Self)
     }
 }
 
@@ -97,115 +250,92 @@ impl TryFrom<Vec<u8>> for Device {
     type Error = Error;
 
     fn try_from(buffer: Vec<u8>) -> Result<Self, Self::Error> {
-        const OFFSET: usize = 5;
-        let length = buffer[4] as usize;
-        let mut index = OFFSET;
+        let frame = Frame::parse(&buffer, FrameLayout::STANDARD)?;
+        Ok(Self::decode_serial(frame.payload)?)
+    }
+}
+
+impl Device {
+    /// Decode the protobuf payload of a `GET_SERIAL` response
+    pub fn decode_serial(payload: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = Reader::new(payload);
         let mut device = Self::default();
-        while index < length + OFFSET {
-            let field = buffer[index];
-            index += 1;
-
-            match field >> 3 {
-                1 => {
-                    let bytes = vec![buffer[index]];
-                    device.supplier = U32::try_from(bytes)?.0;
-                    index += 1;
-                }
-                2 => {
-                    let bytes = vec![buffer[index]];
-                    device.year = U32::try_from(bytes)?.0;
-                    index += 1;
-                }
-                3 => {
-                    let bytes = vec![buffer[index]];
-                    device.week = U32::try_from(bytes)?.0;
-                    index += 1;
-                }
-                4 => {
-                    let bytes = vec![buffer[index]];
-                    device.product = U32::try_from(bytes)?.0;
-                    index += 1;
-                }
-                5 => {
-                    let bytes = vec![buffer[index]];
-                    device.revision = U32::try_from(bytes)?.0;
-                    index += 1;
-                }
-                6 => {
-                    let mut bytes = vec![buffer[index]];
-                    while buffer[index] >> 3 != 0 {
-                        index += 1;
-                        bytes.push(buffer[index]);
-                    }
-                    device.product_id = U32::try_from(bytes)?.0;
-                    index += 1;
-                }
+
+        while !reader.is_empty() {
+            let key = reader.read_key()?;
+            match key.field {
+                1 => device.supplier = reader.read_u8(key)?,
+                2 => device.year = reader.read_u32(key)?,
+                3 => device.week = reader.read_u32(key)?,
+                4 => device.product = reader.read_u8(key)?,
+                5 => device.revision = reader.read_u32(key)?,
+                6 => device.product_id = reader.read_u32(key)?,
                 7 => {
-                    let bytes = vec![buffer[index]];
-                    let discriminant = U32::try_from(bytes)?.0 as usize;
+                    let discriminant = reader.read_u32(key)? as usize;
                     device.stage = Stage::from_repr(discriminant).unwrap_or_default();
-                    index += 1;
-                }
-                9 => {
-                    let bytes = vec![buffer[index]];
-                    device.variant = Some(U32::try_from(bytes)?.0);
-                    index += 1;
-                }
-                10 => {
-                    let bytes = vec![buffer[index]];
-                    device.pcb_design = Some(U32::try_from(bytes)?.0);
-                    index += 1;
-                }
-                11 => {
-                    let bytes = vec![buffer[index]];
-                    device.minor_rev = Some(U32::try_from(bytes)?.0);
-                    index += 1;
-                }
-                _ => {
-                    // Skip unknown field
-                    let wire_type = field & 7;
-                    match wire_type {
-                        0 => index += 1,
-                        1 => index += 8,
-                        2 => {
-                            let length = buffer[index] as usize;
-                            index += length + 1;
-                        }
-                        3 => {
-                            // Skip nested fields
-                            while buffer[index] & 7 != 4 {
-                                index += 1;
-                            }
-                            index += 1;
-                        }
-                        5 => index += 4,
-                        _ => bail!("Invalid wire type"),
-                    }
                 }
+                9 => device.variant = Some(reader.read_u32(key)?),
+                10 => device.pcb_design = Some(reader.read_u32(key)?),
+                11 => device.minor_rev = Some(reader.read_u32(key)?),
+                _ => reader.skip(key)?, // Skip unknown field
             }
         }
 
         Ok(device)
     }
+
+    /// Number of profile slots on the keyboard, if it reported them
+    #[must_use]
+    pub fn profile_slots(&self) -> Option<u8> {
+        self.profile_count.or_else(|| {
+            self.digital_profile_count?
+                .checked_add(self.analog_profile_count?)
+        })
+    }
+
+    /// Whether the profile index is a digital profile, if the keyboard reported them
+    #[must_use]
+    pub fn is_digital_profile(&self, profile_index: ProfileIndex) -> Option<bool> {
+        let digital_profile_count = self.digital_profile_count?;
+        let profile_index = u8::try_from(profile_index).ok()?;
+
+        Some(profile_index < digital_profile_count)
+    }
+
+    /// The saved profile names the keyboard has slots for
+    #[must_use]
+    pub fn available_profiles(&self) -> &[String] {
+        let slots = self
+            .profile_slots()
+            .map_or(self.profiles.len(), usize::from);
+
+        &self.profiles[..slots.min(self.profiles.len())]
+    }
+
+    /// Check the keyboard has a slot for the profile index
+    ///
+    /// Keyboards that didn't report their profile count accept any non-negative index.
+    pub fn check_profile_index(&self, profile_index: ProfileIndex) -> Result<()> {
+        let index = u8::try_from(profile_index)
+            .map_err(|_| Error::InvalidProfileIndex { profile_index })?;
+
+        match self.profile_slots() {
+            Some(profile_count) if index >= profile_count => Err(Error::ProfileOutOfRange {
+                profile_index,
+                profile_count,
+            }),
+            _ => Ok(()),
+        }
+    }
 }
 
-impl From<&Device> for DeviceID {
-    fn from(device: &Device) -> Self {
-        // These must match exactly what the Wooting firmware reports (Wootility var KeyboardType)
-        let keyboard_type = match device.model_name.as_str() {
-            "Wooting One" => 0,
-            "Wooting Two" => 1,
-            "Wooting Two LE" => 2,
-            "Wooting Two HE" => 3,
-            "Wooting 60HE" => 4,
-            "Wooting 60HE (ARM)" => 5,
-            "Wooting Two HE (ARM)" => 6,
-            "Wooting UwU" => 7,
-            "Wooting UwU RGB" => 8,
-            "Wooting 60HE+" => 9,
-            "Wooting 80HE" => 10,
-            &_ => 11,
-        };
+impl TryFrom<&Device> for DeviceID {
+    type Error = Error;
+
+    fn try_from(device: &Device) -> Result<Self> {
+        let keyboard_type = keyboard_type(device).ok_or_else(|| Error::UnknownModel {
+            model_name: device.model_name.clone(),
+        })?;
 
         let device_id = format!(
             "{}{}{}{}{}{}{}{}{}",
@@ -220,311 +350,332 @@ impl From<&Device> for DeviceID {
             device.variant.map_or(String::new(), |v| v.to_string()),
         );
 
-        Self(device_id)
+        Ok(Self(device_id))
     }
 }
 
 impl From<&Device> for DeviceSerial {
     fn from(device: &Device) -> Self {
-        Self(format!(
-            "A{:02X}B{:02}{:02}W{:02X}{}{}{}{}{}{:05}",
-            device.supplier,
-            device.year,
-            device.week,
-            device.product,
-            device
-                .pcb_design
-                .map_or_else(String::new, |pcb_design| format!("T{pcb_design:02}")),
-            device.revision,
-            device
-                .minor_rev
-                .map_or_else(String::new, |minor_rev| format!("{minor_rev:02}")),
-            device
-                .variant
-                .map_or_else(String::new, |variant| format!("S{variant:02}")),
-            device.stage,
-            device.product_id
-        ))
+        Self(SerialFields::from(device).to_string())
     }
 }
 
 impl TryFrom<DeviceID> for Device {
-    type Error = anyhow::Error;
+    type Error = Error;
 
     fn try_from(device_id: DeviceID) -> Result<Self> {
-        unsafe {
-            rgb::wooting_usb_disconnect(false);
-            rgb::wooting_usb_find_keyboard();
-
-            for device_index in 0..WOOTING_RGB_MAX_DEVICES {
-                if !rgb::wooting_usb_select_device(device_index) {
-                    continue;
-                }
-
-                let device = get_active_device()?;
-                if device_id == DeviceID::from(&device) {
-                    return Ok(device);
-                }
-            }
-        }
-
-        bail!("Device ({device_id}) not found")
+        run(move |manager| {
+            manager
+                .find(|device| DeviceID::try_from(device).is_ok_and(|id| id == device_id))?
+                .ok_or_else(|| Error::DeviceNotFound {
+                    device: device_id.to_string(),
+                })
+        })
     }
 }
 
 impl TryFrom<DeviceSerial> for Device {
-    type Error = anyhow::Error;
+    type Error = Error;
 
     fn try_from(device_serial: DeviceSerial) -> Result<Self> {
-        unsafe {
-            rgb::wooting_usb_disconnect(false);
-            rgb::wooting_usb_find_keyboard();
+        run(move |manager| {
+            manager
+                .find(|device| device_serial == DeviceSerial::from(device))?
+                .ok_or_else(|| Error::DeviceNotFound {
+                    device: device_serial.to_string(),
+                })
+        })
+    }
+}
 
-            for device_index in 0..WOOTING_RGB_MAX_DEVICES {
-                if !rgb::wooting_usb_select_device(device_index) {
-                    continue;
-                }
+/* Backend */
 
-                let device = get_active_device()?;
-                if device_serial == DeviceSerial::from(&device) {
-                    return Ok(device);
-                }
-            }
-        }
+/// The hidraw backend when enabled, otherwise the Wooting RGB SDK
+#[cfg(feature = "hid")]
+fn default_backend() -> Box<dyn DeviceBackend> {
+    Box::new(HidBackend::default())
+}
+
+#[cfg(all(feature = "rgb", not(feature = "hid")))]
+fn default_backend() -> Box<dyn DeviceBackend> {
+    Box::new(RgbBackend)
+}
+
+#[cfg(not(any(feature = "rgb", feature = "hid")))]
+fn default_backend() -> Box<dyn DeviceBackend> {
+    Box::new(SimulatedBackend::default())
+}
+
+/// Replace the backend used for all device I/O
+///
+/// Defaults to the hidraw backend with the `hid` feature, the Wooting RGB SDK with the `rgb`
+/// feature, and the simulated backend without either.
+pub fn set_backend(backend: impl DeviceBackend + 'static) {
+    let _ = run(move |manager| {
+        *RESET_HANDLE.lock() = Some(backend.reset_handle());
+        manager.set_backend(Box::new(backend));
+        Ok(())
+    });
+}
 
-        bail!("Device ({device_serial}) not found")
+/// Restore the profile lighting of every keyboard and release them
+///
+/// This bypasses the device worker and the manager, so it is safe to call from a panic or
+/// signal handler even while a command is in progress.
+pub fn reset_devices() {
+    let reset_handle = RESET_HANDLE.lock().clone();
+    if let Some(reset_handle) = reset_handle {
+        reset_handle();
     }
 }
 
+/// Forget the cached devices, the devices are rescanned on next use
+pub fn rescan_devices() -> Result<()> {
+    run(DeviceManager::rescan)
+}
+
 /* Getters */
 
-#[allow(clippy::too_many_lines)]
+/// The selected device, or the first device if none is selected, as of the last scan
 pub fn get_active_device() -> Result<Device> {
-    unsafe {
-        /* Response Bytes (standard reports)
-         * 0-1 Magic Word
-         * 2   Command
-         * 3   Unknown
-         * 4   Length
-         * 5-L Buffer
-         *
-         * Multi-report responses include a report ID at byte 0, shifting the
-         * fields above by +1 and using magic word 0xD1DA.
-         */
-        let response_size = rgb::wooting_usb_get_response_size() as usize;
-        let uses_multi_report = rgb::wooting_usb_use_multi_report();
-        let report_offset = usize::from(uses_multi_report);
-        let extra_data_offset = usize::from(uses_multi_report);
-        let expected_magic_word = if uses_multi_report {
-            MAGIC_WORD_V3
-        } else {
-            MAGIC_WORD_V2
-        };
-
-        let mut buffer = vec![0u8; response_size];
-        let response = rgb::wooting_usb_send_feature_with_response(
-            buffer.as_mut_ptr(),
-            response_size,
-            GET_SERIAL,
-            0,
-            0,
-            0,
-            2,
-        );
+    run(|manager| {
+        let device_serial = manager.selected_or_first()?;
+        manager.select(&device_serial)
+    })
+}
 
-        #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
-        if response != response_size as i32 {
-            bail!("Invalid response length: got {response}, expected {response_size}");
-        }
+/// Read the serial and model of the selected device, see `read_capabilities` for the rest
+fn read_active_device(backend: &mut dyn DeviceBackend) -> Result<Device> {
+    let mut device = query(backend, &GetSerial)?;
+    device.model_name = backend.model_name().ok_or(Error::InvalidModelName)?;
+    device.usb_product_id = backend.usb_product_id();
 
-        let normalized = if report_offset == 0 {
-            buffer
-        } else {
-            buffer[report_offset..].to_vec()
-        };
+    Ok(device)
+}
 
-        let magic_word = u16::from_le_bytes([normalized[0], normalized[1]]);
-        if magic_word != expected_magic_word {
-            bail!("Invalid response type");
-        }
+/// Read the firmware version and profile counts of the selected device
+///
+/// Older firmware doesn't answer these, the device is still usable without them.
+fn read_capabilities(backend: &mut dyn DeviceBackend, device: &mut Device) {
+    device.firmware_version = query(backend, &GetVersion).ok();
+    device.profile_count = query(backend, &GetRgbProfileCount).ok();
+    device.digital_profile_count = query(backend, &GetDigitalProfilesCount).ok();
+    device.analog_profile_count = query(backend, &GetAnalogProfilesCount).ok();
+}
 
-        let command = normalized[2];
-        if command != GET_SERIAL {
-            bail!("Invalid response command");
-        }
+/// Rescan and return all connected devices
+pub fn get_all_devices() -> Result<Vec<Device>> {
+    run(|manager| {
+        manager.rescan()?;
+        manager.devices()
+    })
+}
 
-        let length = normalized[4] as usize;
-        let data_start = 5 + extra_data_offset;
+pub fn get_active_profile_index() -> Result<ProfileIndex> {
+    run(|manager| manager.with_selected(read_active_profile_index))
+}
 
-        #[cfg(debug_assertions)]
-        println!(
-            "Serial Buffer: {:?}",
-            &normalized[data_start..data_start + length]
-        );
-        let wooting_usb_meta = *rgb::wooting_usb_get_meta();
-        let c_str_model_name = CStr::from_ptr(wooting_usb_meta.model);
-        let mut parse_buffer = normalized;
-        if uses_multi_report && parse_buffer.len() > data_start {
-            parse_buffer.remove(5);
-        }
-        let mut device = Device::try_from(parse_buffer)?;
-        device.model_name = c_str_model_name.to_str()?.replace("Lekker Edition", "LE");
+fn read_active_profile_index(backend: &mut dyn DeviceBackend) -> Result<ProfileIndex> {
+    query(backend, &GetCurrentKeyboardProfileIndex)
+}
 
-        Ok(device)
-    }
+pub fn get_device_indices() -> Result<DeviceIndices> {
+    run(DeviceManager::device_indices)
 }
 
-pub fn get_all_devices() -> Result<Vec<Device>> {
-    let mut devices = Vec::new();
+/* Setters */
 
-    unsafe {
-        rgb::wooting_usb_disconnect(false);
-        if !rgb::wooting_usb_find_keyboard() {
-            bail!("Failed to find keyboard(s)")
-        }
+pub fn set_active_profile_index(
+    profile_index: ProfileIndex,
+    options: &SwitchOptions,
+) -> Result<()> {
+    let options = options.clone();
+    run(move |manager| {
+        let device_serial = manager.selected_or_first()?;
+        manager.set_profile_index(&device_serial, profile_index, &options)
+    })
+}
 
-        for device_index in 0..WOOTING_RGB_MAX_DEVICES {
-            if !rgb::wooting_usb_select_device(device_index) {
-                continue;
-            }
+/// Activate the profile and wait for the keyboard to report it, retrying with a capped
+/// exponential backoff, then swap the lighting effects as the lighting mode says
+fn write_active_profile_index(
+    backend: &mut dyn DeviceBackend,
+    profile_index: ProfileIndex,
+    lighting: LightingMode,
+    options: &SwitchOptions,
+) -> Result<()> {
+    let index =
+        u8::try_from(profile_index).map_err(|_| Error::InvalidProfileIndex { profile_index })?;
+    let timeout = Duration::from_millis(options.send_timeout_ms);
 
-            let device = get_active_device()?;
-            devices.push(device);
-        }
+    if lighting.switches_profile() {
+        write_profile(backend, profile_index, options)?;
+    }
 
-        rgb::wooting_rgb_reset_rgb();
+    if lighting.swaps_lighting() {
+        send(backend, &WootDevResetAll)?;
+        wait_until_ready(backend, Command::WootDevResetAll, timeout)?;
+        send(
+            backend,
+            &RefreshRgbColors {
+                profile_index: index,
+            },
+        )?;
     }
 
-    Ok(devices)
-}
-
-#[must_use]
-pub fn get_active_profile_index() -> ProfileIndex {
-    unsafe {
-        let response_size = rgb::wooting_usb_get_response_size() as usize;
-        let uses_multi_report = rgb::wooting_usb_use_multi_report();
-        let report_offset = if uses_multi_report { 1 } else { 0 };
-        let extra_data_offset = if uses_multi_report { 1 } else { 0 };
-
-        let mut buff = vec![0u8; response_size];
-        let response = rgb::wooting_usb_send_feature_with_response(
-            buff.as_mut_ptr(),
-            response_size,
-            GET_CURRENT_KEYBOARD_PROFILE_INDEX,
-            0,
-            0,
-            0,
-            0,
-        );
+    Ok(())
+}
 
-        #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
-        if response == response_size as i32 {
-            let is_v2 = rgb::wooting_usb_use_v2_interface();
-            let data_offset = report_offset + if is_v2 { 5 } else { 4 } + extra_data_offset;
-            buff.get(data_offset)
-                .copied()
-                .unwrap_or(ProfileIndex::MAX as u8) as ProfileIndex
-        } else {
-            ProfileIndex::MAX
+/// Activate the profile and reload it once the keyboard reports it
+fn write_profile(
+    backend: &mut dyn DeviceBackend,
+    profile_index: ProfileIndex,
+    options: &SwitchOptions,
+) -> Result<()> {
+    let index =
+        u8::try_from(profile_index).map_err(|_| Error::InvalidProfileIndex { profile_index })?;
+    let timeout = Duration::from_millis(options.send_timeout_ms);
+
+    let mut attempt = 0;
+    loop {
+        send(
+            backend,
+            &ActivateProfile {
+                profile_index: index,
+            },
+        )?;
+        let active_profile_index = wait_for_profile_index(backend, profile_index, timeout);
+        if active_profile_index == Some(profile_index) {
+            break;
         }
-    }
-}
 
-pub fn get_device_indices() -> Result<DeviceIndices> {
-    let mut device_indices = DeviceIndices::new();
+        if attempt >= options.retries.min(MAX_RETRIES) {
+            return Err(Error::SwitchFailed {
+                expected: profile_index,
+                got:      active_profile_index,
+                attempts: attempt + 1,
+            });
+        }
 
-    unsafe {
-        rgb::wooting_usb_disconnect(false);
-        rgb::wooting_usb_find_keyboard();
+        // The worker is blocked meanwhile, so the backoff stops growing after a few attempts
+        attempt += 1;
+        let backoff = RETRY_BACKOFF * 2u32.saturating_pow(attempt);
+        std::thread::sleep(backoff.min(MAX_RETRY_BACKOFF));
+    }
 
-        for device_index in 0..WOOTING_RGB_MAX_DEVICES {
-            if !rgb::wooting_usb_select_device(device_index) {
-                continue;
-            }
+    send(
+        backend,
+        &ReloadProfile {
+            profile_index: index,
+        },
+    )?;
+    wait_until_ready(backend, Command::ReloadProfile, timeout)
+}
 
-            let device = get_active_device()?;
-            let device_serial = DeviceSerial::from(&device);
-            let profile_index = get_active_profile_index();
-            device_indices.insert(device_serial, profile_index);
+/// Poll the active profile index until it matches or the timeout expires,
+/// returning the last index read
+fn wait_for_profile_index(
+    backend: &mut dyn DeviceBackend,
+    profile_index: ProfileIndex,
+    timeout: Duration,
+) -> Option<ProfileIndex> {
+    let start = Instant::now();
+    loop {
+        let active_profile_index = read_active_profile_index(backend).ok();
+        if active_profile_index == Some(profile_index) || start.elapsed() >= timeout {
+            return active_profile_index;
         }
 
-        rgb::wooting_rgb_reset_rgb();
+        std::thread::sleep(ACK_POLL_INTERVAL);
     }
-
-    Ok(device_indices)
 }
 
-/* Setters */
-
-pub fn set_active_profile_index(
-    profile_index: ProfileIndex,
-    send_sleep_ms: u64,
-    swap_lighting: bool,
+/// Poll the keyboard until it responds, the firmware handles commands in order so a
+/// response means the previous command has been processed
+fn wait_until_ready(
+    backend: &mut dyn DeviceBackend,
+    command: Command,
+    timeout: Duration,
 ) -> Result<()> {
-    let profile_index = u8::try_from(profile_index)?;
-
-    unsafe {
-        rgb::wooting_usb_send_feature(ACTIVATE_PROFILE, 0, 0, 0, profile_index);
-        std::thread::sleep(Duration::from_millis(send_sleep_ms));
-        rgb::wooting_usb_send_feature(RELOAD_PROFILE, 0, 0, 0, profile_index);
-
-        if swap_lighting {
-            std::thread::sleep(Duration::from_millis(send_sleep_ms));
-            rgb::wooting_usb_send_feature(WOOT_DEV_RESET_ALL, 0, 0, 0, 0);
-            std::thread::sleep(Duration::from_millis(send_sleep_ms));
-            rgb::wooting_usb_send_feature(REFRESH_RGB_COLORS, 0, 0, 0, profile_index);
+    let start = Instant::now();
+    while read_active_profile_index(backend).is_err() {
+        if start.elapsed() >= timeout {
+            return Err(Error::Timeout { command });
         }
+
+        std::thread::sleep(ACK_POLL_INTERVAL);
     }
 
     Ok(())
 }
 
-pub fn set_device_indices(
-    mut device_indices: DeviceIndices,
-    send_sleep_ms: u64,
-    swap_lighting: bool,
+/// Switch the device to the profile index
+pub fn set_device_profile_index(
+    device_serial: &DeviceSerial,
+    profile_index: ProfileIndex,
+    options: &SwitchOptions,
 ) -> Result<()> {
-    unsafe {
-        rgb::wooting_usb_disconnect(false);
-        rgb::wooting_usb_find_keyboard();
-
-        for device_index in 0..WOOTING_RGB_MAX_DEVICES {
-            if !rgb::wooting_usb_select_device(device_index) {
-                continue;
-            }
+    let device_serial = device_serial.clone();
+    let options = options.clone();
+    run(move |manager| manager.set_profile_index(&device_serial, profile_index, &options))
+}
 
-            let device = get_active_device()?;
-            let device_serial = DeviceSerial::from(&device);
-            if let Some(profile_index) = device_indices.remove(&device_serial) {
-                // Silently ignore negative profile indexes as a way to skip updating devices
-                let _ = set_active_profile_index(profile_index, send_sleep_ms, swap_lighting);
-            }
-        }
+/// Apply the profile indices to the connected devices
+///
+/// Negative profile indices and disconnected devices are skipped. Every device is
+/// attempted even if one fails, the first error is returned afterwards.
+pub fn set_device_indices(device_indices: &DeviceIndices, options: &SwitchOptions) -> Result<()> {
+    let device_indices = device_indices.clone();
+    let options = options.clone();
+    run(move |manager| manager.set_device_indices(&device_indices, &options))
+}
 
-        rgb::wooting_rgb_reset_rgb();
-    }
+/// Light keys on the device until the overlay is cleared, every other key is turned off
+pub fn set_overlay(device_serial: &DeviceSerial, key_colors: &[KeyColor]) -> Result<()> {
+    let device_serial = device_serial.clone();
+    let key_colors = key_colors.to_vec();
+    run(move |manager| manager.set_overlay(&device_serial, key_colors))
+}
 
-    Ok(())
+/// Release the overlay of the device back to the profile lighting
+pub fn clear_overlay(device_serial: &DeviceSerial) -> Result<()> {
+    let device_serial = device_serial.clone();
+    run(move |manager| manager.clear_overlay(&device_serial))
 }
 
 /* Helpers */
 
 pub fn select_device_serial(device_serial: &DeviceSerial) -> Result<Device> {
-    unsafe {
-        rgb::wooting_usb_disconnect(false);
-        rgb::wooting_usb_find_keyboard();
-
-        for device_index in 0..WOOTING_RGB_MAX_DEVICES {
-            if !rgb::wooting_usb_select_device(device_index) {
-                continue;
-            }
+    let device_serial = device_serial.clone();
+    run(move |manager| manager.select(&device_serial))
+}
 
-            let device = get_active_device()?;
-            if device_serial == &DeviceSerial::from(&device) {
-                return Ok(device);
-            }
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resets_devices_while_the_manager_is_in_use() {
+        let backend = SimulatedBackend::new(2);
+        set_backend(backend.clone());
+        let device_serial = DeviceSerial::from(&get_active_device().unwrap());
+        let key_colors = [KeyColor {
+            row:    1,
+            column: 1,
+            color:  RgbColor::default(),
+        }];
+        set_overlay(&device_serial, &key_colors).unwrap();
+        assert_eq!(backend.state().lock().devices[0].key_colors, key_colors);
+
+        let manager = MANAGER.lock();
+        reset_devices();
+        drop(manager);
+
+        let state = backend.state().lock().clone();
+        assert!(state
+            .devices
+            .iter()
+            .all(|device| device.key_colors.is_empty()));
+        assert_eq!(state.selected, None);
     }
-
-    bail!("Device ({device_serial}) not found")
 }
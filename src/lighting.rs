@@ -0,0 +1,378 @@
+use std::{collections::HashMap, fmt, hash::BuildHasher, str::FromStr};
+
+use derive_more::{Display, Error};
+use serde::{Deserialize, Serialize};
+
+use crate::ProfileIndex;
+
+/* Constants */
+
+/// Size of the lighting matrix of a full size keyboard, smaller keyboards leave cells unused
+pub const RGB_ROWS: u8 = 6;
+pub const RGB_COLUMNS: u8 = 21;
+
+/// Stands in for the number key of the profile being switched to
+pub const PROFILE_KEY: &str = "Profile";
+
+// https://github.com/WootingKb/wooting-rgb-sdk/blob/master/resources/keyboard-matrix-rows-columns.png
+const KEYS: &[(&str, u8, u8)] = &[
+    ("Esc", 0, 0),
+    ("F1", 0, 2),
+    ("F2", 0, 3),
+    ("F3", 0, 4),
+    ("F4", 0, 5),
+    ("F5", 0, 6),
+    ("F6", 0, 7),
+    ("F7", 0, 8),
+    ("F8", 0, 9),
+    ("F9", 0, 10),
+    ("F10", 0, 11),
+    ("F11", 0, 12),
+    ("F12", 0, 13),
+    ("PrintScreen", 0, 14),
+    ("Pause", 0, 15),
+    ("ScrollLock", 0, 16),
+    ("A1", 0, 17),
+    ("A2", 0, 18),
+    ("A3", 0, 19),
+    ("Mode", 0, 20),
+    ("Backtick", 1, 0),
+    ("1", 1, 1),
+    ("2", 1, 2),
+    ("3", 1, 3),
+    ("4", 1, 4),
+    ("5", 1, 5),
+    ("6", 1, 6),
+    ("7", 1, 7),
+    ("8", 1, 8),
+    ("9", 1, 9),
+    ("0", 1, 10),
+    ("Minus", 1, 11),
+    ("Equals", 1, 12),
+    ("Backspace", 1, 13),
+    ("Insert", 1, 14),
+    ("Home", 1, 15),
+    ("PageUp", 1, 16),
+    ("NumLock", 1, 17),
+    ("NumDivide", 1, 18),
+    ("NumMultiply", 1, 19),
+    ("NumSubtract", 1, 20),
+    ("Tab", 2, 0),
+    ("Q", 2, 1),
+    ("W", 2, 2),
+    ("E", 2, 3),
+    ("R", 2, 4),
+    ("T", 2, 5),
+    ("Y", 2, 6),
+    ("U", 2, 7),
+    ("I", 2, 8),
+    ("O", 2, 9),
+    ("P", 2, 10),
+    ("LeftBracket", 2, 11),
+    ("RightBracket", 2, 12),
+    ("Backslash", 2, 13),
+    ("Delete", 2, 14),
+    ("End", 2, 15),
+    ("PageDown", 2, 16),
+    ("Num7", 2, 17),
+    ("Num8", 2, 18),
+    ("Num9", 2, 19),
+    ("NumAdd", 2, 20),
+    ("CapsLock", 3, 0),
+    ("A", 3, 1),
+    ("S", 3, 2),
+    ("D", 3, 3),
+    ("F", 3, 4),
+    ("G", 3, 5),
+    ("H", 3, 6),
+    ("J", 3, 7),
+    ("K", 3, 8),
+    ("L", 3, 9),
+    ("Semicolon", 3, 10),
+    ("Quote", 3, 11),
+    ("IsoHash", 3, 12),
+    ("Enter", 3, 13),
+    ("Num4", 3, 17),
+    ("Num5", 3, 18),
+    ("Num6", 3, 19),
+    ("LeftShift", 4, 0),
+    ("IsoBackslash", 4, 1),
+    ("Z", 4, 2),
+    ("X", 4, 3),
+    ("C", 4, 4),
+    ("V", 4, 5),
+    ("B", 4, 6),
+    ("N", 4, 7),
+    ("M", 4, 8),
+    ("Comma", 4, 9),
+    ("Period", 4, 10),
+    ("Slash", 4, 11),
+    ("RightShift", 4, 13),
+    ("Up", 4, 15),
+    ("Num1", 4, 17),
+    ("Num2", 4, 18),
+    ("Num3", 4, 19),
+    ("NumEnter", 4, 20),
+    ("LeftCtrl", 5, 0),
+    ("LeftMeta", 5, 1),
+    ("LeftAlt", 5, 2),
+    ("Space", 5, 6),
+    ("RightAlt", 5, 10),
+    ("RightMeta", 5, 11),
+    ("Fn", 5, 12),
+    ("RightCtrl", 5, 13),
+    ("Left", 5, 14),
+    ("Down", 5, 15),
+    ("Right", 5, 16),
+    ("Num0", 5, 18),
+    ("NumDecimal", 5, 19),
+];
+
+/* Structures */
+
+/// A color written as `#RRGGBB`
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct RgbColor {
+    pub red:   u8,
+    pub green: u8,
+    pub blue:  u8,
+}
+
+#[derive(Clone, Debug, Display, Eq, Error, PartialEq)]
+#[display("Invalid color {color}, expected #RRGGBB")]
+pub struct ColorError {
+    pub color: String,
+}
+
+/// A key of the lighting matrix and its color
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct KeyColor {
+    pub row:    u8,
+    pub column: u8,
+    pub color:  RgbColor,
+}
+
+/// Options for briefly lighting keys on a keyboard after a profile switch
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default)]
+pub struct FlashOptions {
+    pub color:       RgbColor,
+    pub duration_ms: u64,
+    /// Key names, see `key_position`, every other key is turned off during the flash
+    pub keys:        Vec<String>,
+}
+
+/* Implementations */
+
+impl Default for FlashOptions {
+    fn default() -> Self {
+        Self {
+            color:       RgbColor {
+                red:   0,
+                green: 255,
+                blue:  0,
+            },
+            duration_ms: 500,
+            keys:        vec![String::from(PROFILE_KEY)],
+        }
+    }
+}
+
+impl FlashOptions {
+    /// The keys to light for a switch to the profile index, unknown key names are skipped
+    #[must_use]
+    pub fn key_colors(&self, profile_index: ProfileIndex) -> Vec<KeyColor> {
+        self.keys
+            .iter()
+            .filter_map(|key| {
+                if key.eq_ignore_ascii_case(PROFILE_KEY) {
+                    profile_key(profile_index)
+                } else {
+                    key_position(key)
+                }
+            })
+            .map(|(row, column)| KeyColor {
+                row,
+                column,
+                color: self.color,
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for RgbColor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:02X}{:02X}{:02X}", self.red, self.green, self.blue)
+    }
+}
+
+impl FromStr for RgbColor {
+    type Err = ColorError;
+
+    fn from_str(color: &str) -> Result<Self, Self::Err> {
+        let error = || ColorError {
+            color: color.to_owned(),
+        };
+
+        let hex = color.strip_prefix('#').ok_or_else(error)?;
+        if hex.len() != 6 || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return Err(error());
+        }
+
+        let channel = |offset: usize| u8::from_str_radix(&hex[offset..offset + 2], 16);
+        Ok(Self {
+            red:   channel(0).map_err(|_| error())?,
+            green: channel(2).map_err(|_| error())?,
+            blue:  channel(4).map_err(|_| error())?,
+        })
+    }
+}
+
+impl TryFrom<String> for RgbColor {
+    type Error = ColorError;
+
+    fn try_from(color: String) -> Result<Self, Self::Error> {
+        color.parse()
+    }
+}
+
+impl From<RgbColor> for String {
+    fn from(color: RgbColor) -> Self {
+        color.to_string()
+    }
+}
+
+/* Functions */
+
+/// The row and column of a key in the lighting matrix, key names are case insensitive
+#[must_use]
+pub fn key_position(name: &str) -> Option<(u8, u8)> {
+    KEYS.iter()
+        .find(|(key, ..)| key.eq_ignore_ascii_case(name))
+        .map(|&(_, row, column)| (row, column))
+}
+
+/// The keys of a key name to color map in matrix order, unknown key names are skipped
+#[must_use]
+pub fn key_colors<S: BuildHasher>(colors: &HashMap<String, RgbColor, S>) -> Vec<KeyColor> {
+    let mut key_colors = colors
+        .iter()
+        .filter_map(|(key, &color)| {
+            let (row, column) = key_position(key)?;
+            Some(KeyColor { row, column, color })
+        })
+        .collect::<Vec<_>>();
+
+    // Sorted so equal maps give equal keys
+    key_colors.sort_by_key(|key_color| (key_color.row, key_color.column));
+
+    key_colors
+}
+
+/// The names of the keys in the lighting matrix
+pub fn key_names() -> impl Iterator<Item = &'static str> {
+    KEYS.iter().map(|&(key, ..)| key)
+}
+
+/// The number key of the profile index, the first profile is on the 1 key
+fn profile_key(profile_index: ProfileIndex) -> Option<(u8, u8)> {
+    let number = u8::try_from(profile_index).ok()?.checked_add(1)?;
+    match number {
+        1..=9 => key_position(&number.to_string()),
+        10 => key_position("0"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GREEN: RgbColor = RgbColor {
+        red:   0,
+        green: 255,
+        blue:  0,
+    };
+
+    #[test]
+    fn parses_colors() {
+        assert_eq!("#00FF00".parse(), Ok(GREEN));
+        assert_eq!("#00ff00".parse(), Ok(GREEN));
+        assert_eq!(
+            "#1A2B3C".parse(),
+            Ok(RgbColor {
+                red:   0x1A,
+                green: 0x2B,
+                blue:  0x3C,
+            })
+        );
+
+        for color in [
+            "", "#", "00FF00", "#00FF0", "#00FF000", "#00FG00", "#+0FF00", "#ÿÿÿ",
+        ] {
+            assert_eq!(
+                color.parse::<RgbColor>(),
+                Err(ColorError {
+                    color: color.to_owned(),
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn round_trips_colors_with_the_formatter() {
+        assert_eq!(GREEN.to_string(), "#00FF00");
+        assert_eq!(String::from(GREEN).parse(), Ok(GREEN));
+        assert_eq!(RgbColor::try_from(String::from("#00FF00")), Ok(GREEN));
+    }
+
+    #[test]
+    fn maps_profiles_to_number_keys() {
+        assert_eq!(profile_key(0), key_position("1"));
+        assert_eq!(profile_key(8), key_position("9"));
+        assert_eq!(profile_key(9), key_position("0"));
+        assert_eq!(profile_key(10), None);
+        assert_eq!(profile_key(-1), None);
+        assert_eq!(profile_key(ProfileIndex::MAX), None);
+    }
+
+    #[test]
+    fn orders_key_colors_by_position() {
+        let colors = HashMap::from([
+            (String::from("space"), GREEN),
+            (String::from("Esc"), GREEN),
+            (String::from("Unknown"), GREEN),
+            (String::from("A"), RgbColor::default()),
+        ]);
+
+        let positions = key_colors(&colors)
+            .into_iter()
+            .map(|key_color| (key_color.row, key_color.column, key_color.color))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            positions,
+            [(0, 0, GREEN), (3, 1, RgbColor::default()), (5, 6, GREEN),]
+        );
+    }
+
+    #[test]
+    fn flashes_the_profile_key() {
+        let flash = FlashOptions {
+            keys: vec![
+                String::from("profile"),
+                String::from("Esc"),
+                String::from("Unknown"),
+            ],
+            ..Default::default()
+        };
+
+        let positions = flash
+            .key_colors(2)
+            .into_iter()
+            .map(|key_color| (key_color.row, key_color.column))
+            .collect::<Vec<_>>();
+        assert_eq!(positions, [(1, 3), (0, 0)]);
+        assert_eq!(flash.key_colors(10).len(), 1);
+    }
+}
@@ -1,17 +1,22 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use std::{backtrace::Backtrace, collections::HashMap, ffi::OsStr, str::FromStr, time::Duration};
+use std::{
+    backtrace::Backtrace,
+    collections::{HashMap, HashSet},
+    ffi::OsStr,
+    str::FromStr,
+    time::{Duration, Instant},
+};
 
 use active_win_pos_rs::ActiveWindow;
 use anyhow::Result;
 use app::MainApp;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use parking_lot::RwLock;
-use regex::Regex;
 use tauri::{
     image::Image,
-    menu::{CheckMenuItem, MenuBuilder, MenuItem},
+    menu::{CheckMenuItem, Menu, MenuBuilder, MenuItem},
     tray::{MouseButton, TrayIconBuilder, TrayIconEvent},
     AppHandle,
     Builder,
@@ -22,19 +27,33 @@ use tauri::{
 use tauri_plugin_autostart::{MacosLauncher::LaunchAgent, ManagerExt};
 use tauri_plugin_egui::Builder as EguiPluginBuilder;
 use tauri_plugin_updater::UpdaterExt;
-use wildflower::Pattern;
 #[cfg(target_os = "windows")]
 use windows::Win32::System::Console::{AttachConsole, FreeConsole, ATTACH_PARENT_PROCESS};
 use wootility::Wootility;
 use wooting_profile_switcher as wps;
-use wooting_rgb_sys as rgb;
-use wps::{DeviceID, DeviceIndices, DeviceSerial, ProfileIndex};
+use wps::{
+    Device,
+    DeviceEvent,
+    DeviceID,
+    DeviceIndices,
+    DeviceSerial,
+    KeyColor,
+    LightingMode,
+    ProfileIndex,
+    SimulatedBackend,
+    SIMULATED_PROFILE_COUNT,
+};
 
-use crate::config::{Config, Rule};
+use crate::{
+    config::Config,
+    rules::{self, PendingSwitch, RuleSet},
+};
 
 mod app;
 mod config;
+mod rules;
 mod theme;
+mod window_list;
 mod wootility;
 
 #[derive(Debug, Parser)]
@@ -54,6 +73,21 @@ struct Args {
     /// Pause the active window scanning at startup.
     #[arg(long, default_value_t = false)]
     paused: bool,
+
+    /// Emulate the given number of keyboards instead of using connected devices.
+    /// Intended for testing without hardware.
+    #[arg(long)]
+    simulate: Option<u8>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Check the rules in the config for mistakes and exit.
+    /// Exits with a non-zero status if any were found.
+    Check,
 }
 
 type AppRuntime = tauri::Wry;
@@ -64,6 +98,29 @@ struct TrayMenuState {
     profile_items: HashMap<String, CheckMenuItem<AppRuntime>>,
 }
 
+#[derive(Debug, Clone, Default)]
+struct DeviceState {
+    /// Serials of the currently connected keyboards
+    connected:       HashSet<DeviceSerial>,
+    /// Device indices of the currently matched rule
+    device_indices:  DeviceIndices,
+    /// Active profile index of each connected keyboard, as reported by the keyboards
+    profile_indices: DeviceIndices,
+    /// Lighting modes of the currently matched rule
+    lighting_modes:  HashMap<DeviceSerial, LightingMode>,
+    /// Overlay of the currently matched rule
+    overlay:         Vec<KeyColor>,
+}
+
+/// What to apply once the active window kept focus for the switch delay
+#[derive(Debug)]
+struct QueuedSwitch {
+    /// Overlay of the matched rule, empty to release it
+    overlay:  Vec<KeyColor>,
+    /// Device indices and lighting modes, none if no rule matched
+    profiles: Option<(DeviceIndices, HashMap<DeviceSerial, LightingMode>)>,
+}
+
 #[derive(Debug, Clone, Default)]
 struct ActiveMatchInfo {
     app_name: String,
@@ -72,6 +129,24 @@ struct ActiveMatchInfo {
     win_name: String,
 }
 
+impl From<ActiveWindow> for ActiveMatchInfo {
+    fn from(active_window: ActiveWindow) -> Self {
+        let bin_name = active_window
+            .process_path
+            .file_name()
+            .and_then(OsStr::to_str)
+            .map(String::from)
+            .unwrap_or_default();
+
+        Self {
+            app_name: active_window.app_name,
+            bin_name,
+            bin_path: active_window.process_path.display().to_string(),
+            win_name: active_window.title,
+        }
+    }
+}
+
 #[allow(clippy::too_many_lines)]
 fn main() -> Result<()> {
     #[cfg(target_os = "linux")]
@@ -79,17 +154,22 @@ fn main() -> Result<()> {
         std::env::set_var("GDK_BACKEND", "x11");
     }
 
+    let args = Args::parse();
+    if let Some(Command::Check) = args.command {
+        return check_rules();
+    }
+
     // Reset the keyboard if the program panics
-    std::panic::set_hook(Box::new(|info| unsafe {
+    std::panic::set_hook(Box::new(|info| {
         eprintln!("Panic: {info}");
         eprintln!("Backtrace:\n{}", Backtrace::force_capture());
-        rgb::wooting_rgb_reset();
+        wps::reset_devices();
         std::process::exit(1);
     }));
 
     // Reset the keyboard if the program is killed/terminated
-    ctrlc::set_handler(move || unsafe {
-        rgb::wooting_rgb_reset();
+    ctrlc::set_handler(move || {
+        wps::reset_devices();
         std::process::exit(1);
     })?;
 
@@ -97,13 +177,20 @@ fn main() -> Result<()> {
         .plugin(tauri_plugin_autostart::init(LaunchAgent, None))
         .plugin(tauri_plugin_single_instance::init(|_app, _argv, _cwd| {}))
         .plugin(tauri_plugin_updater::Builder::new().build())
-        .setup(|app| {
+        .setup(move |app| {
             #[cfg(target_os = "macos")] // Hide the macOS dock icon
             app.set_activation_policy(tauri::ActivationPolicy::Accessory);
             app.wry_plugin(EguiPluginBuilder::new(app.handle().clone()));
-            app.manage(RwLock::new(Args::parse()));
-            app.manage(RwLock::new(Config::load()?));
+            let simulated = args.simulate.is_some();
+            if let Some(device_count) = args.simulate {
+                println!("Simulating {device_count} device(s)");
+                wps::set_backend(SimulatedBackend::new(device_count));
+            }
+
+            app.manage(RwLock::new(args));
+            app.manage(RwLock::new(Config::load_and_apply()?));
             app.manage(RwLock::new(ActiveMatchInfo::default()));
+            app.manage(RwLock::new(DeviceState::default()));
             app.manage(RwLock::new(MainApp::new(app.handle())));
             if let Err(error) = MainApp::init(app.handle()) {
                 eprintln!("Failed to initialize main app window: {error}");
@@ -119,63 +206,103 @@ fn main() -> Result<()> {
                 (args.profile_index, args.device_serial.clone())
             };
             if let Some(profile_index) = profile_index {
-                if let Some(device_serial) = device_serial {
-                    wps::select_device_serial(&device_serial)?;
+                // The process exits right away, a flash would never be restored
+                let mut switch_options = config.read().switch_options();
+                switch_options.flash.clear();
+
+                let result = device_serial
+                    .map_or(Ok(()), |device_serial| {
+                        wps::select_device_serial(&device_serial).map(drop)
+                    })
+                    .and_then(|()| wps::set_active_profile_index(profile_index, &switch_options));
+
+                if let Err(error) = result {
+                    report_device_error(&error);
+                    std::process::exit(1);
                 }
 
-                let (send_sleep_ms, swap_lighting) = {
-                    let config = config.read();
-                    (config.send_sleep_ms, config.swap_lighting)
-                };
-                let _ = wps::set_active_profile_index(profile_index, send_sleep_ms, swap_lighting);
-
                 println!("Profile Index Updated");
                 std::process::exit(0);
             }
 
-            println!("Scanning Wootility for devices and profiles to save");
-            match Wootility::load() {
-                Ok(mut wootility) => {
-                    let devices = match wps::get_all_devices() {
-                        Ok(devices) => devices,
-                        Err(error) => {
-                            eprintln!("{error}");
-                            std::process::exit(1);
-                            // TODO: Add a GUI popup
-                        }
-                    };
-                    println!("Found Devices: {devices:#?}");
-
-                    let mut config = config.write();
-                    config.devices = devices
-                        .into_iter()
-                        .filter_map(|mut device| {
-                            let device_id = DeviceID::from(&device);
-                            let device_serial = DeviceSerial::from(&device);
-                            println!("Device ID: {device_id}");
-                            println!("Device Serial: {device_serial}");
-                            println!("Found Profiles: {:#?}", wootility.profiles);
-
-                            device.profiles = wootility
-                                .profiles
-                                .devices
-                                .remove(&device_id)?
-                                .into_iter()
-                                .map(|profile| profile.details.name)
-                                .collect();
-
-                            Some((device_serial, device))
-                        })
-                        .collect();
-                    config.save()?;
-                }
-                Err(error) => {
-                    eprintln!("Failed to access Wootility local storage");
-                    eprintln!("Please make sure Wootility isn't running");
-                    eprintln!("{error}"); // TODO: Add a GUI popup
+            if simulated {
+                let devices = wps::get_all_devices()?;
+                println!("Simulated Devices: {devices:#?}");
+
+                // Simulated devices don't exist in Wootility, use placeholder profile names
+                config.write().devices = devices
+                    .into_iter()
+                    .map(|mut device| {
+                        device.profiles = simulated_profiles();
+                        (DeviceSerial::from(&device), device)
+                    })
+                    .collect();
+            } else {
+                println!("Scanning Wootility for devices and profiles to save");
+                match Wootility::load() {
+                    Ok(mut wootility) => {
+                        let devices = match wps::get_all_devices() {
+                            Ok(devices) => devices,
+                            Err(error) => {
+                                eprintln!("{error}");
+                                std::process::exit(1);
+                                // TODO: Add a GUI popup
+                            }
+                        };
+                        println!("Found Devices: {devices:#?}");
+
+                        let mut config = config.write();
+                        config.devices = devices
+                            .into_iter()
+                            .filter_map(|mut device| {
+                                let device_serial = DeviceSerial::from(&device);
+                                println!("Device Serial: {device_serial}");
+
+                                let device_id = match DeviceID::try_from(&device) {
+                                    Ok(device_id) => device_id,
+                                    Err(error) => {
+                                        // Keep the profile names mapped manually in the config,
+                                        // a new device is added without any
+                                        report_device_error(&error);
+                                        if let Some(saved) = config.devices.get(&device_serial) {
+                                            device.profiles.clone_from(&saved.profiles);
+                                        }
+                                        return Some((device_serial, device));
+                                    }
+                                };
+                                println!("Device ID: {device_id}");
+                                println!("Found Profiles: {:#?}", wootility.profiles);
+
+                                device.profiles = wootility
+                                    .profiles
+                                    .devices
+                                    .remove(&device_id)?
+                                    .into_iter()
+                                    .map(|profile| profile.details.name)
+                                    .collect();
+
+                                Some((device_serial, device))
+                            })
+                            .collect();
+                        config.save()?;
+                    }
+                    Err(error) => {
+                        eprintln!("Failed to access Wootility local storage");
+                        eprintln!("Please make sure Wootility isn't running");
+                        eprintln!("{error}"); // TODO: Add a GUI popup
+                    }
                 }
             }
 
+            // The tray and GUI only list the connected devices
+            let profile_indices = wps::get_device_indices().unwrap_or_default();
+            {
+                let device_state = app.state::<RwLock<DeviceState>>();
+                let mut device_state = device_state.write();
+                device_state.connected = profile_indices.keys().cloned().collect();
+                device_state.profile_indices = profile_indices;
+            }
+
             // Enable or disable auto-launch on startup
             let auto_launch_manager = app.autolaunch();
             let auto_launch = config.read().auto_launch;
@@ -226,58 +353,12 @@ fn main() -> Result<()> {
                 }
             }
 
-            let app_handle = app.handle().clone();
-            let mut profile_items = HashMap::new();
-            let show_item =
-                MenuItem::with_id(&app_handle, "show", "Show Window", true, None::<&str>)?;
-            let mut tray_menu = MenuBuilder::new(&app_handle).item(&show_item).separator();
-
-            let devices = config.read().devices.clone();
-            for (device_serial, device) in devices {
-                let serial_number = device_serial.to_string();
-                let title = if config.read().show_serial {
-                    &serial_number
-                } else {
-                    &device.model_name
-                };
-
-                let menu_item =
-                    MenuItem::with_id(&app_handle, &serial_number, title, false, None::<&str>)?;
-                tray_menu = tray_menu.item(&menu_item);
-
-                for (i, title) in device.profiles.iter().enumerate() {
-                    let id = format!("{device_serial}|{i}");
-                    let menu_item =
-                        CheckMenuItem::with_id(&app_handle, &id, title, true, false, None::<&str>)?;
-                    profile_items.insert(id, menu_item.clone());
-                    tray_menu = tray_menu.item(&menu_item);
-                }
-
-                tray_menu = tray_menu.separator();
-            }
-
-            let pause_item =
-                MenuItem::with_id(&app_handle, "pause", "Pause Scanning", true, None::<&str>)?;
-            let reload_item =
-                MenuItem::with_id(&app_handle, "reload", "Reload Config", true, None::<&str>)?;
-            let quit_item =
-                MenuItem::with_id(&app_handle, "quit", "Quit Program", true, None::<&str>)?;
-            tray_menu = tray_menu
-                .item(&pause_item)
-                .item(&reload_item)
-                .separator()
-                .item(&quit_item);
-
-            let tray_menu = tray_menu.build()?;
-            app.manage(RwLock::new(TrayMenuState {
-                show_item: show_item.clone(),
-                pause_item: pause_item.clone(),
-                profile_items,
-            }));
+            let (tray_menu, tray_menu_state) = build_tray_menu(app.handle())?;
+            app.manage(RwLock::new(tray_menu_state));
 
             let tray_app_handle = app.handle().clone();
             let tray_icon = Image::from_bytes(include_bytes!("../icons/icon.png"))?;
-            TrayIconBuilder::new()
+            TrayIconBuilder::with_id(TRAY_ID)
                 .menu(&tray_menu)
                 .icon(tray_icon)
                 .show_menu_on_left_click(false)
@@ -295,7 +376,9 @@ fn main() -> Result<()> {
                             app.exit(0);
                         }
                         "reload" => {
-                            *config.write() = Config::load().expect("Failed to reload config");
+                            *config.write() =
+                                Config::load_and_apply().expect("Failed to reload config");
+                            refresh_tray_menu(app);
                         }
                         "pause" => {
                             let paused = args.read().paused;
@@ -312,44 +395,41 @@ fn main() -> Result<()> {
                                 return;
                             };
 
-                            let Ok(device_serial) = DeviceSerial::from_str(serial_number);
-                            let Ok(profile_index) = profile_index.parse::<ProfileIndex>() else {
+                            let Ok(device_serial) = DeviceSerial::from_str(serial_number) else {
                                 return;
                             };
-                            let Ok(profile_index_usize) = usize::try_from(profile_index) else {
+                            let Ok(profile_index) = profile_index.parse::<ProfileIndex>() else {
                                 return;
                             };
-
-                            if wps::select_device_serial(&device_serial).is_err() {
-                                return;
-                            }
-
-                            let _ = wps::set_active_profile_index(
-                                profile_index,
-                                config.read().send_sleep_ms,
-                                config.read().swap_lighting,
-                            );
-
-                            let mut args = args.write();
-                            args.device_serial = Some(device_serial);
-                            args.profile_index = Some(profile_index);
-
-                            let items = tray_state.read();
-                            if let Some(active_device_serial) = args.device_serial.clone() {
-                                let devices = config.read().devices.clone();
-                                for (device_serial, device) in devices {
-                                    if device_serial != active_device_serial {
-                                        continue;
-                                    }
-
-                                    for i in 0..device.profiles.len() {
-                                        let item_id = format!("{device_serial}|{i}");
-                                        if let Some(item) = items.profile_items.get(&item_id) {
-                                            let _ = item.set_checked(i == profile_index_usize);
-                                        }
+                            // Wait for the switch off the event loop so the tray stays responsive
+                            let switch_options = config.read().switch_options();
+                            let pending = wps::submit({
+                                let device_serial = device_serial.clone();
+                                move |manager| {
+                                    manager.set_profile_index(
+                                        &device_serial,
+                                        profile_index,
+                                        &switch_options,
+                                    )
+                                }
+                            });
+
+                            let app = app.clone();
+                            std::thread::spawn(move || {
+                                match pending.wait() {
+                                    Ok(()) => {
+                                        let device_state = app.state::<RwLock<DeviceState>>();
+                                        device_state
+                                            .write()
+                                            .profile_indices
+                                            .insert(device_serial, profile_index);
                                     }
+                                    Err(error) => report_device_error(&error),
                                 }
-                            }
+
+                                // Clicking toggles the item, restore the checkmarks either way
+                                update_tray_checks(&app);
+                            });
                         }
                     }
                 })
@@ -362,7 +442,7 @@ fn main() -> Result<()> {
                         toggle_main_window(&tray_app_handle);
                     }
                 })
-                .build(&app_handle)?;
+                .build(app.handle())?;
 
             // Attempt to hide the Windows console
             #[cfg(target_os = "windows")]
@@ -377,6 +457,10 @@ fn main() -> Result<()> {
         .run(move |app, event| match event {
             RunEvent::Ready => {
                 let app = app.clone();
+                let watcher_app = app.clone();
+                std::thread::spawn(move || {
+                    device_watcher_task(&watcher_app);
+                });
                 std::thread::spawn(move || {
                     active_window_polling_task(&app).unwrap();
                 });
@@ -401,6 +485,105 @@ fn main() -> Result<()> {
 }
 
 const MAIN_WINDOW_LABEL: &str = env!("CARGO_PKG_NAME");
+const TRAY_ID: &str = "main";
+
+// Builds the system tray menu from the connected devices
+fn build_tray_menu(app: &AppHandle) -> tauri::Result<(Menu<AppRuntime>, TrayMenuState)> {
+    let args = app.state::<RwLock<Args>>();
+    let config = app.state::<RwLock<Config>>();
+    let device_state = app.state::<RwLock<DeviceState>>();
+
+    let visible = app
+        .get_window(MAIN_WINDOW_LABEL)
+        .and_then(|window| window.is_visible().ok())
+        .unwrap_or(false);
+    let show_title = if visible {
+        "Hide Window"
+    } else {
+        "Show Window"
+    };
+
+    let mut profile_items = HashMap::new();
+    let show_item = MenuItem::with_id(app, "show", show_title, true, None::<&str>)?;
+    let mut tray_menu = MenuBuilder::new(app).item(&show_item).separator();
+
+    let devices = config.read().devices.clone();
+    let (connected, profile_indices) = {
+        let device_state = device_state.read();
+        (
+            device_state.connected.clone(),
+            device_state.profile_indices.clone(),
+        )
+    };
+
+    for (device_serial, device) in devices {
+        if !connected.contains(&device_serial) {
+            continue;
+        }
+
+        let profile_index = profile_indices.get(&device_serial).copied();
+
+        let serial_number = device_serial.to_string();
+        let title = if config.read().show_serial {
+            &serial_number
+        } else {
+            &device.model_name
+        };
+
+        let menu_item = MenuItem::with_id(app, &serial_number, title, false, None::<&str>)?;
+        tray_menu = tray_menu.item(&menu_item);
+
+        for (i, title) in device.available_profiles().iter().enumerate() {
+            let id = format!("{device_serial}|{i}");
+            let checked = profile_index.and_then(|i| usize::try_from(i).ok()) == Some(i);
+            let menu_item = CheckMenuItem::with_id(app, &id, title, true, checked, None::<&str>)?;
+            profile_items.insert(id, menu_item.clone());
+            tray_menu = tray_menu.item(&menu_item);
+        }
+
+        tray_menu = tray_menu.separator();
+    }
+
+    let pause_title = if args.read().paused {
+        "Resume Scanning"
+    } else {
+        "Pause Scanning"
+    };
+
+    let pause_item = MenuItem::with_id(app, "pause", pause_title, true, None::<&str>)?;
+    let reload_item = MenuItem::with_id(app, "reload", "Reload Config", true, None::<&str>)?;
+    let quit_item = MenuItem::with_id(app, "quit", "Quit Program", true, None::<&str>)?;
+    tray_menu = tray_menu
+        .item(&pause_item)
+        .item(&reload_item)
+        .separator()
+        .item(&quit_item);
+
+    let tray_menu_state = TrayMenuState {
+        show_item,
+        pause_item,
+        profile_items,
+    };
+
+    Ok((tray_menu.build()?, tray_menu_state))
+}
+
+// Rebuilds the system tray menu after the connected devices changed
+fn refresh_tray_menu(app: &AppHandle) {
+    let Some(tray) = app.tray_by_id(TRAY_ID) else {
+        return;
+    };
+
+    match build_tray_menu(app) {
+        Ok((tray_menu, tray_menu_state)) => {
+            *app.state::<RwLock<TrayMenuState>>().write() = tray_menu_state;
+            let _ = tray.set_menu(Some(tray_menu));
+        }
+        Err(error) => {
+            eprintln!("Failed to rebuild the tray menu: {error}");
+        }
+    }
+}
 
 fn update_show_menu(app: &AppHandle, visible: bool) {
     let tray_state = app.state::<RwLock<TrayMenuState>>();
@@ -433,135 +616,370 @@ fn toggle_main_window(app: &AppHandle) {
     }
 }
 
+// Checks the active profile of each device in the system tray menu
+fn update_tray_checks(app: &AppHandle) {
+    let config = app.state::<RwLock<Config>>();
+    let device_state = app.state::<RwLock<DeviceState>>();
+    let tray_state = app.state::<RwLock<TrayMenuState>>();
+
+    let devices = config.read().devices.clone();
+    let profile_indices = device_state.read().profile_indices.clone();
+    let tray_items = tray_state.read();
+    for (device_serial, device) in devices {
+        let profile_index = profile_indices
+            .get(&device_serial)
+            .and_then(|profile_index| usize::try_from(*profile_index).ok());
+
+        for i in 0..device.available_profiles().len() {
+            let id = format!("{device_serial}|{i}");
+            if let Some(item_handle) = tray_items.profile_items.get(&id) {
+                let _ = item_handle.set_checked(profile_index == Some(i));
+            }
+        }
+    }
+}
+
+// Placeholder profile names for simulated devices, which don't exist in Wootility
+fn simulated_profiles() -> Vec<String> {
+    (1..=SIMULATED_PROFILE_COUNT)
+        .map(|i| format!("Profile {i}"))
+        .collect()
+}
+
+// Adds a device that wasn't connected at startup to the config with its profile names
+fn add_device(app: &AppHandle, mut device: Device) {
+    let simulated = app.state::<RwLock<Args>>().read().simulate.is_some();
+    device.profiles = if simulated {
+        simulated_profiles()
+    } else {
+        let mut wootility = match Wootility::load() {
+            Ok(wootility) => wootility,
+            Err(error) => {
+                eprintln!("Failed to access Wootility local storage");
+                eprintln!("Please make sure Wootility isn't running");
+                eprintln!("{error}");
+                return;
+            }
+        };
+
+        match DeviceID::try_from(&device) {
+            Ok(device_id) => {
+                let Some(profiles) = wootility.profiles.devices.remove(&device_id) else {
+                    return;
+                };
+
+                profiles
+                    .into_iter()
+                    .map(|profile| profile.details.name)
+                    .collect()
+            }
+            Err(error) => {
+                // Add the device without profile names, they can be set in the config
+                report_device_error(&error);
+                Vec::new()
+            }
+        }
+    };
+
+    let config = app.state::<RwLock<Config>>();
+    let mut config = config.write();
+    config.devices.insert(DeviceSerial::from(&device), device);
+    if let Err(error) = config.save() {
+        eprintln!("Failed to save config: {error}");
+    }
+}
+
+// Updates the firmware details of a saved device, they change with firmware updates
+fn update_device_details(app: &AppHandle, device: &Device) {
+    let config = app.state::<RwLock<Config>>();
+    let mut config = config.write();
+    let Some(saved) = config.devices.get_mut(&DeviceSerial::from(device)) else {
+        return;
+    };
+
+    let details = (
+        device.firmware_version,
+        device.profile_count,
+        device.digital_profile_count,
+        device.analog_profile_count,
+    );
+    let saved_details = (
+        saved.firmware_version,
+        saved.profile_count,
+        saved.digital_profile_count,
+        saved.analog_profile_count,
+    );
+    if details == saved_details {
+        return;
+    }
+
+    saved.firmware_version = device.firmware_version;
+    saved.profile_count = device.profile_count;
+    saved.digital_profile_count = device.digital_profile_count;
+    saved.analog_profile_count = device.analog_profile_count;
+    if let Err(error) = config.save() {
+        eprintln!("Failed to save config: {error}");
+    }
+}
+
+// Watches for keyboards being connected and disconnected and re-applies the matched rule
+fn device_watcher_task(app: &AppHandle) {
+    let config = app.state::<RwLock<Config>>();
+    let device_state = app.state::<RwLock<DeviceState>>();
+
+    let watch_options = config.read().watch_options();
+    for event in wps::watch(&watch_options) {
+        match event {
+            DeviceEvent::Connected {
+                device,
+                profile_index,
+            } => {
+                let device_serial = DeviceSerial::from(&device);
+                println!("Device Connected: {device_serial}");
+
+                {
+                    let mut device_state = device_state.write();
+                    device_state.connected.insert(device_serial.clone());
+                    if let Some(profile_index) = profile_index {
+                        device_state
+                            .profile_indices
+                            .insert(device_serial.clone(), profile_index);
+                    }
+                }
+
+                if config.read().devices.contains_key(&device_serial) {
+                    update_device_details(app, &device);
+                } else {
+                    add_device(app, device);
+                }
+
+                refresh_tray_menu(app);
+
+                let overlay = device_state.read().overlay.clone();
+                if !overlay.is_empty() {
+                    if let Err(error) = wps::set_overlay(&device_serial, &overlay) {
+                        report_device_error(&error);
+                    }
+                }
+
+                // The keyboard comes back on its default profile, restore the matched rule
+                let profile_index = device_state
+                    .read()
+                    .device_indices
+                    .get(&device_serial)
+                    .copied()
+                    .filter(|profile_index| *profile_index >= 0);
+                if let Some(profile_index) = profile_index {
+                    let mut switch_options = config.read().switch_options();
+                    switch_options
+                        .lighting_modes
+                        .clone_from(&device_state.read().lighting_modes);
+                    if let Err(error) = wps::set_device_profile_index(
+                        &device_serial,
+                        profile_index,
+                        &switch_options,
+                    ) {
+                        report_device_error(&error);
+                    }
+                }
+            }
+            DeviceEvent::Disconnected { serial } => {
+                println!("Device Disconnected: {serial}");
+
+                {
+                    let mut device_state = device_state.write();
+                    device_state.connected.remove(&serial);
+                    device_state.profile_indices.remove(&serial);
+                }
+
+                refresh_tray_menu(app);
+            }
+            DeviceEvent::ProfileChanged { serial, from, to } => {
+                println!("Device Profile Changed: {serial} ({from} -> {to})");
+                device_state.write().profile_indices.insert(serial, to);
+                update_tray_checks(app);
+            }
+        }
+    }
+}
+
 // Polls the active window to matching rules and applies the keyboard profile
 fn active_window_polling_task(app: &AppHandle) -> Result<()> {
     let args = app.state::<RwLock<Args>>();
     let config = app.state::<RwLock<Config>>();
     let active_info = app.state::<RwLock<ActiveMatchInfo>>();
+    let device_state = app.state::<RwLock<DeviceState>>();
 
     let mut last_active_window = ActiveWindow::default();
-    let mut last_device_indices = wps::get_device_indices()?;
+    let mut last_device_indices = wps::get_device_indices().unwrap_or_default();
+    let mut last_lighting_modes = HashMap::new();
+    let mut rule_set = RuleSet::default();
+    let mut rule_set_revision = None;
+    let mut pending_switch = PendingSwitch::<QueuedSwitch>::default();
 
     loop {
         let loop_sleep_ms = config.read().loop_sleep_ms;
         std::thread::sleep(Duration::from_millis(loop_sleep_ms));
 
-        // Update the selected profile system tray menu item
-        let (active_profile_index, active_device_serial, paused) = {
-            let args = args.read();
-            (args.profile_index, args.device_serial.clone(), args.paused)
-        };
-
-        if let (Some(active_profile_index), Some(active_device_serial)) =
-            (active_profile_index, active_device_serial)
-        {
-            if let Ok(active_profile_index_usize) = usize::try_from(active_profile_index) {
-                let devices = config.read().devices.clone();
-                let tray_state = app.state::<RwLock<TrayMenuState>>();
-                let tray_items = tray_state.read();
-                for (device_serial, device) in devices {
-                    if device_serial != active_device_serial {
-                        continue;
-                    }
-
-                    for i in 0..device.profiles.len() {
-                        let id = format!("{device_serial}|{i}");
-                        if let Some(item_handle) = tray_items.profile_items.get(&id) {
-                            let _ = item_handle.set_checked(i == active_profile_index_usize);
-                        }
-                    }
-                }
+        // Recompile the rules only after the config was loaded or saved
+        let revision = Config::revision();
+        if rule_set_revision != Some(revision) {
+            let config = config.read();
+            rule_set = RuleSet::new(&config.rules, config.match_strategy);
+            rule_set_revision = Some(revision);
+            for error in rule_set.errors() {
+                eprintln!("{error}");
             }
         }
 
+        let paused = args.read().paused;
         let Ok(active_window) = active_win_pos_rs::get_active_window() else {
             continue;
         };
 
-        if active_window == last_active_window {
-            continue;
-        }
+        if active_window != last_active_window {
+            last_active_window = active_window.clone();
+            let window = ActiveMatchInfo::from(active_window);
+
+            println!("Updated Active Window:");
+            println!("- App Name: {}", window.app_name);
+            println!("- Bin Name: {}", window.bin_name);
+            println!("- Bin Path: {}", window.bin_path);
+            println!("- Win Name: {}", window.win_name);
+
+            *active_info.write() = window.clone();
+
+            // Resolved once per focus change, the rules are compiled and don't change meanwhile
+            let resolution = rule_set.resolve(&window);
+            let rule = resolution.rule();
+            let switch_delay = resolution.switch_delay(config.read().switch_delay_ms);
+            let profiles = rule.map(|_| {
+                let mut device_indices = DeviceIndices::new();
+                let mut lighting_modes = HashMap::new();
+                for (device_serial, profile_index, lighting_mode) in resolution.profiles() {
+                    device_indices.insert(device_serial.clone(), profile_index);
+                    if let Some(lighting_mode) = lighting_mode {
+                        lighting_modes.insert(device_serial.clone(), lighting_mode);
+                    }
+                }
 
-        last_active_window = active_window.clone();
-        let active_window_bin_path = active_window.process_path.display().to_string();
-        let active_window_bin_name = active_window
-            .process_path
-            .file_name()
-            .and_then(OsStr::to_str)
-            .map(String::from)
-            .unwrap_or_default();
+                (device_indices, lighting_modes)
+            });
 
-        {
-            let mut active_info = active_info.write();
-            active_info.app_name.clone_from(&active_window.app_name);
-            active_info.bin_name.clone_from(&active_window_bin_name);
-            active_info.bin_path.clone_from(&active_window_bin_path);
-            active_info.win_name.clone_from(&active_window.title);
+            let overlay = rule
+                .map(|rule| wps::key_colors(&rule.overlay))
+                .unwrap_or_default();
+            let switch = QueuedSwitch { overlay, profiles };
+            pending_switch.queue(switch, switch_delay, Instant::now());
         }
 
-        if paused {
+        // Wait until the window kept focus for the whole switch delay
+        let Some(switch) = pending_switch.take_due(Instant::now(), paused) else {
             continue;
+        };
+
+        // The overlay is released as soon as its rule stops matching
+        if switch.overlay != device_state.read().overlay {
+            apply_overlay(app, switch.overlay);
         }
 
-        let rules = config.read().rules.clone();
-        let Some(device_indices) = find_match(active_window, rules) else {
+        let Some((device_indices, lighting_modes)) = switch.profiles else {
             continue;
         };
+        {
+            let mut device_state = device_state.write();
+            device_state.device_indices.clone_from(&device_indices);
+            device_state.lighting_modes.clone_from(&lighting_modes);
+        }
 
-        if device_indices == last_device_indices {
+        if device_indices == last_device_indices && lighting_modes == last_lighting_modes {
             continue;
         }
 
         last_device_indices.clone_from(&device_indices);
+        last_lighting_modes.clone_from(&lighting_modes);
 
         println!("Updated Device Indices: {device_indices:#?}");
-        wps::set_device_indices(
-            device_indices,
-            config.read().send_sleep_ms,
-            config.read().swap_lighting,
-        )?;
+        let mut switch_options = config.read().switch_options();
+        switch_options.lighting_modes = lighting_modes;
+        if let Err(error) = wps::set_device_indices(&device_indices, &switch_options) {
+            report_device_error(&error);
+
+            // Forget the applied indices so the switch is retried on the next focus change
+            last_device_indices.clear();
+        }
     }
 }
 
-// Find the first matching device indices for the given active window
-#[allow(clippy::uninlined_format_args)]
-fn find_match(active_window: ActiveWindow, rules: Vec<Rule>) -> Option<DeviceIndices> {
-    type RulePropFn = fn(Rule) -> Option<String>;
-
-    let active_window_bin_path = active_window.process_path.display().to_string();
-    let active_window_bin_name = active_window
-        .process_path
-        .file_name()
-        .and_then(OsStr::to_str)
-        .map(String::from)
-        .unwrap_or_default();
-
-    println!("Updated Active Window:");
-    println!("- App Name: {}", active_window.app_name);
-    println!("- Bin Name: {}", active_window_bin_name);
-    println!("- Bin Path: {}", active_window_bin_path);
-    println!("- Win Name: {}", active_window.title);
-
-    let match_active_window: Vec<(RulePropFn, String)> = vec![
-        (|rule| rule.match_app_name, active_window.app_name),
-        (|rule| rule.match_bin_name, active_window_bin_name),
-        (|rule| rule.match_bin_path, active_window_bin_path),
-        (|rule| rule.match_win_name, active_window.title),
-    ];
-
-    for rule in rules {
-        for (rule_prop_fn, active_prop) in &match_active_window {
-            if let Some(rule_prop) = rule_prop_fn(rule.clone()) {
-                if Pattern::new(&rule_prop.replace('\\', "\\\\")).matches(active_prop) {
-                    return Some(rule.device_indices);
-                } else if let Ok(re) = Regex::new(&rule_prop) {
-                    if re.is_match(active_prop) {
-                        return Some(rule.device_indices);
-                    }
-                }
-            }
+// Lights the overlay on every connected keyboard, or releases it if empty
+fn apply_overlay(app: &AppHandle, overlay: Vec<KeyColor>) {
+    let device_state = app.state::<RwLock<DeviceState>>();
+    let connected = device_state.read().connected.clone();
+    for device_serial in &connected {
+        let result = if overlay.is_empty() {
+            wps::clear_overlay(device_serial)
+        } else {
+            wps::set_overlay(device_serial, &overlay)
+        };
+
+        if let Err(error) = result {
+            report_device_error(&error);
         }
     }
 
-    None
+    device_state.write().overlay = overlay;
+}
+
+// Prints the problems found in the rules, exits with an error if there are any
+fn check_rules() -> Result<()> {
+    #[cfg(target_os = "windows")]
+    unsafe {
+        let _ = AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+
+    let lints = rules::lint(&Config::parse()?);
+    if lints.is_empty() {
+        println!("No problems found");
+        return Ok(());
+    }
+
+    for lint in &lints {
+        println!("{lint}");
+    }
+
+    std::process::exit(1);
+}
+
+// Print a device error with a hint on how to resolve it
+fn report_device_error(error: &wps::Error) {
+    eprintln!("{error}");
+    match error {
+        wps::Error::NoDevices | wps::Error::DeviceNotFound { .. } => {
+            eprintln!("Please make sure the keyboard is connected");
+        }
+        wps::Error::Timeout { .. }
+        | wps::Error::SendFailed { .. }
+        | wps::Error::SwitchFailed { .. } => {
+            eprintln!("The keyboard may be busy, please try again");
+        }
+        wps::Error::WorkerStopped => {
+            eprintln!("Please restart the application");
+        }
+        wps::Error::InvalidProfileIndex { .. } | wps::Error::ProfileOutOfRange { .. } => {
+            eprintln!("Please check the profile indices in the config");
+        }
+        wps::Error::UnknownModel { .. } => {
+            eprintln!(
+                "Add the model to \"models\" in the config to look up its Wootility profiles,"
+            );
+            eprintln!("or set the profile names of the device in the config manually");
+        }
+        wps::Error::InvalidResponse { .. }
+        | wps::Error::ProtocolMismatch { .. }
+        | wps::Error::UnexpectedCommand { .. }
+        | wps::Error::InvalidModelName
+        | wps::Error::Decode(_) => {
+            eprintln!("The keyboard firmware may not be supported, please report this issue");
+        }
+    }
 }
@@ -0,0 +1,899 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    query,
+    read_active_device,
+    read_active_profile_index,
+    read_capabilities,
+    write_active_profile_index,
+    Device,
+    DeviceBackend,
+    DeviceIndices,
+    DeviceSerial,
+    Error,
+    FirmwareVersion,
+    FlashOptions,
+    GetSerial,
+    KeyColor,
+    ProfileIndex,
+    Result,
+    SwitchOptions,
+    WOOTING_RGB_MAX_DEVICES,
+};
+
+/// Caches the connected devices and their slots so commands can target a device
+/// without walking the bus
+///
+/// The devices are enumerated on first use and rescanned only when requested or when a
+/// command fails in a way that suggests the slots have changed.
+pub struct DeviceManager {
+    backend:      Box<dyn DeviceBackend>,
+    devices:      HashMap<DeviceSerial, (u8, Device)>,
+    /// Kept across rescans, older firmware only gives up on a query after the read timeout
+    capabilities: HashMap<DeviceSerial, Capabilities>,
+    scanned:      bool,
+    selected:     Option<DeviceSerial>,
+    /// When the flash showing on each device ends
+    flashes:      HashMap<DeviceSerial, Instant>,
+    /// Keys lit on each device until the overlay is cleared
+    overlays:     HashMap<DeviceSerial, Vec<KeyColor>>,
+}
+
+/// What a device reported about its firmware, `None` where it doesn't support the query
+#[derive(Clone, Copy, Debug)]
+struct Capabilities {
+    firmware_version:      Option<FirmwareVersion>,
+    profile_count:         Option<u8>,
+    digital_profile_count: Option<u8>,
+    analog_profile_count:  Option<u8>,
+}
+
+impl Capabilities {
+    fn read(backend: &mut dyn DeviceBackend) -> Self {
+        let mut device = Device::default();
+        read_capabilities(backend, &mut device);
+
+        Self::from(&device)
+    }
+
+    const fn apply(self, device: &mut Device) {
+        device.firmware_version = self.firmware_version;
+        device.profile_count = self.profile_count;
+        device.digital_profile_count = self.digital_profile_count;
+        device.analog_profile_count = self.analog_profile_count;
+    }
+}
+
+impl From<&Device> for Capabilities {
+    fn from(device: &Device) -> Self {
+        Self {
+            firmware_version:      device.firmware_version,
+            profile_count:         device.profile_count,
+            digital_profile_count: device.digital_profile_count,
+            analog_profile_count:  device.analog_profile_count,
+        }
+    }
+}
+
+impl DeviceManager {
+    #[must_use]
+    pub fn new(backend: Box<dyn DeviceBackend>) -> Self {
+        Self {
+            backend,
+            devices: HashMap::new(),
+            capabilities: HashMap::new(),
+            scanned: false,
+            selected: None,
+            flashes: HashMap::new(),
+            overlays: HashMap::new(),
+        }
+    }
+
+    /// Replace the backend, the devices are rescanned on next use
+    pub fn set_backend(&mut self, backend: Box<dyn DeviceBackend>) {
+        self.backend = backend;
+        self.capabilities.clear();
+        self.invalidate();
+    }
+
+    /// Forget the cached devices, the devices are rescanned on next use
+    pub fn invalidate(&mut self) {
+        self.devices.clear();
+        self.scanned = false;
+        self.selected = None;
+    }
+
+    pub fn backend(&mut self) -> &mut dyn DeviceBackend {
+        self.backend.as_mut()
+    }
+
+    /// Enumerate the connected devices and read their serials
+    ///
+    /// Finding no devices leaves the cache empty rather than failing, and a keyboard that
+    /// doesn't report its serial is left out until the next scan. The selected device stays
+    /// selected if it is still connected.
+    pub fn rescan(&mut self) -> Result<()> {
+        let selected = self.selected.take();
+        self.invalidate();
+        if !self.backend.find_devices() {
+            self.scanned = true;
+            return Ok(());
+        }
+
+        let mut last_selected = None;
+        for slot in 0..WOOTING_RGB_MAX_DEVICES {
+            if !self.backend.select_device(slot) {
+                continue;
+            }
+
+            let backend = self.backend.as_mut();
+            let mut device = match read_active_device(backend) {
+                Ok(device) => device,
+                Err(error) => {
+                    eprintln!("Skipping the keyboard in slot {slot}: {error}");
+                    continue;
+                }
+            };
+            let device_serial = DeviceSerial::from(&device);
+            self.capabilities
+                .entry(device_serial.clone())
+                .or_insert_with(|| Capabilities::read(backend))
+                .apply(&mut device);
+
+            self.devices.insert(device_serial.clone(), (slot, device));
+            last_selected = Some(device_serial);
+        }
+
+        // Read the capabilities again once a device comes back, its firmware may have changed
+        let devices = &self.devices;
+        self.capabilities
+            .retain(|device_serial, _| devices.contains_key(device_serial));
+
+        self.release_rgb(last_selected.as_ref());
+        self.scanned = true;
+
+        if let Some(device_serial) = selected {
+            self.select_cached(&device_serial);
+        }
+
+        Ok(())
+    }
+
+    /// Rescan the devices if a keyboard was connected, disconnected or swapped since the
+    /// last scan
+    ///
+    /// Counting the keyboards may enumerate them again and reorder their slots, so the
+    /// serial in each cached slot is read back as well. A keyboard that doesn't answer, while
+    /// asleep or being replugged, is left out by the rescan until it answers again.
+    pub fn refresh(&mut self) -> Result<()> {
+        if self.scanned
+            && usize::from(self.backend.device_count()) == self.devices.len()
+            && self.slots_unchanged()
+        {
+            return Ok(());
+        }
+
+        self.rescan()
+    }
+
+    /// Whether every cached slot still holds the device with the cached serial
+    fn slots_unchanged(&mut self) -> bool {
+        let backend = self.backend.as_mut();
+        let unchanged = self.devices.iter().all(|(device_serial, (slot, _))| {
+            backend.select_device(*slot)
+                && query(backend, &GetSerial)
+                    .is_ok_and(|device| DeviceSerial::from(&device) == *device_serial)
+        });
+
+        if let Some(device_serial) = self.selected.clone() {
+            self.select_cached(&device_serial);
+        }
+
+        unchanged
+    }
+
+    fn ensure_scanned(&mut self) -> Result<()> {
+        if self.scanned {
+            Ok(())
+        } else {
+            self.rescan()
+        }
+    }
+
+    /// The connected devices, ordered by slot
+    pub fn devices(&mut self) -> Result<Vec<Device>> {
+        self.ensure_scanned()?;
+
+        let mut devices = self.devices.values().collect::<Vec<_>>();
+        devices.sort_by_key(|(slot, _)| *slot);
+
+        Ok(devices
+            .into_iter()
+            .map(|(_, device)| device.clone())
+            .collect())
+    }
+
+    pub fn find(&mut self, predicate: impl Fn(&Device) -> bool) -> Result<Option<Device>> {
+        Ok(self.devices()?.into_iter().find(predicate))
+    }
+
+    /// Select the device, all following commands without a serial target it
+    pub fn select(&mut self, device_serial: &DeviceSerial) -> Result<Device> {
+        self.ensure_scanned()?;
+        if let Some(device) = self.select_cached(device_serial) {
+            return Ok(device);
+        }
+
+        // The device may have been connected since the last scan
+        self.rescan()?;
+        self.select_cached(device_serial)
+            .ok_or_else(|| Error::DeviceNotFound {
+                device: device_serial.to_string(),
+            })
+    }
+
+    fn select_cached(&mut self, device_serial: &DeviceSerial) -> Option<Device> {
+        let (slot, device) = self.devices.get(device_serial)?;
+        if !self.backend.select_device(*slot) {
+            return None;
+        }
+
+        let device = device.clone();
+        self.selected = Some(device_serial.clone());
+
+        Some(device)
+    }
+
+    /// The serial of the currently selected device
+    #[must_use]
+    pub const fn selected(&self) -> Option<&DeviceSerial> {
+        self.selected.as_ref()
+    }
+
+    /// Run a command against the device, rescanning and retrying once if it fails
+    pub fn with_device<T>(
+        &mut self,
+        device_serial: &DeviceSerial,
+        mut command: impl FnMut(&mut dyn DeviceBackend) -> Result<T>,
+    ) -> Result<T> {
+        self.select(device_serial)?;
+        match command(self.backend.as_mut()) {
+            Err(error) if is_stale(&error) => {
+                self.rescan()?;
+                self.select(device_serial)?;
+                command(self.backend.as_mut())
+            }
+            result => result,
+        }
+    }
+
+    /// Run a command against the selected device, or the first device if none is selected
+    pub fn with_selected<T>(
+        &mut self,
+        command: impl FnMut(&mut dyn DeviceBackend) -> Result<T>,
+    ) -> Result<T> {
+        let device_serial = self.selected_or_first()?;
+        self.with_device(&device_serial, command)
+    }
+
+    /// The serial of the selected device, or the first device if none is selected
+    pub fn selected_or_first(&mut self) -> Result<DeviceSerial> {
+        if let Some(device_serial) = self.selected.clone() {
+            return Ok(device_serial);
+        }
+
+        let device = self.devices()?.into_iter().next().ok_or(Error::NoDevices)?;
+        Ok(DeviceSerial::from(&device))
+    }
+
+    pub fn profile_index(&mut self, device_serial: &DeviceSerial) -> Result<ProfileIndex> {
+        self.with_device(device_serial, read_active_profile_index)
+    }
+
+    pub fn set_profile_index(
+        &mut self,
+        device_serial: &DeviceSerial,
+        profile_index: ProfileIndex,
+        options: &SwitchOptions,
+    ) -> Result<()> {
+        self.ensure_scanned()?;
+        if let Some((_, device)) = self.devices.get(device_serial) {
+            device.check_profile_index(profile_index)?;
+        }
+
+        let lighting = options.lighting_mode(device_serial);
+        self.with_device(device_serial, |backend| {
+            write_active_profile_index(backend, profile_index, lighting, options)
+        })?;
+
+        // Swapping the lighting repaints the profile colors over the overlay
+        if let Some(flash) = options.flash.get(device_serial) {
+            self.flash(device_serial, profile_index, flash);
+        } else if self.overlays.contains_key(device_serial) {
+            self.restore_rgb(device_serial)?;
+        }
+
+        Ok(())
+    }
+
+    /// Light keys on the device, the profile lighting is restored by `end_flashes` once the
+    /// duration has passed
+    ///
+    /// This is best effort, devices and backends without lighting control are left alone.
+    pub fn flash(
+        &mut self,
+        device_serial: &DeviceSerial,
+        profile_index: ProfileIndex,
+        flash: &FlashOptions,
+    ) {
+        let key_colors = flash.key_colors(profile_index);
+        if key_colors.is_empty() {
+            return;
+        }
+
+        let lit = self.with_device(device_serial, |backend| {
+            Ok(backend.set_key_colors(&key_colors))
+        });
+        if !lit.unwrap_or(false) {
+            return;
+        }
+
+        // A later flash replaces the end of an earlier one still showing
+        let ends = Instant::now() + Duration::from_millis(flash.duration_ms);
+        self.flashes.insert(device_serial.clone(), ends);
+    }
+
+    /// When the next flash ends, if any is showing
+    #[must_use]
+    pub fn next_flash_end(&self) -> Option<Instant> {
+        self.flashes.values().min().copied()
+    }
+
+    /// Restore the profile lighting, or the overlay, of the devices whose flash ended by `now`
+    ///
+    /// The device worker calls this before each job and whenever a flash ends.
+    pub fn end_flashes(&mut self, now: Instant) {
+        let ended = self
+            .flashes
+            .iter()
+            .filter(|(_, ends)| **ends <= now)
+            .map(|(device_serial, _)| device_serial.clone())
+            .collect::<Vec<_>>();
+
+        for device_serial in ended {
+            self.flashes.remove(&device_serial);
+            // Best effort like the flash itself, the device may have been disconnected
+            let _ = self.restore_rgb(&device_serial);
+        }
+    }
+
+    /// Light keys on the device until the overlay is cleared, every other key is turned off
+    ///
+    /// A flash showing on the device ends on the overlay instead of the profile lighting.
+    pub fn set_overlay(
+        &mut self,
+        device_serial: &DeviceSerial,
+        key_colors: Vec<KeyColor>,
+    ) -> Result<()> {
+        self.overlays.insert(device_serial.clone(), key_colors);
+        if self.flashes.contains_key(device_serial) {
+            return Ok(());
+        }
+
+        self.restore_rgb(device_serial)
+    }
+
+    /// Release the overlay of the device back to the profile lighting
+    pub fn clear_overlay(&mut self, device_serial: &DeviceSerial) -> Result<()> {
+        if self.overlays.remove(device_serial).is_none() || self.flashes.contains_key(device_serial)
+        {
+            return Ok(());
+        }
+
+        self.restore_rgb(device_serial)
+    }
+
+    /// Show the overlay of the device, or the profile lighting if it has none
+    fn restore_rgb(&mut self, device_serial: &DeviceSerial) -> Result<()> {
+        let key_colors = self.overlays.get(device_serial).cloned();
+        self.with_device(device_serial, |backend| {
+            match &key_colors {
+                Some(key_colors) => {
+                    backend.set_key_colors(key_colors);
+                }
+                None => backend.reset_rgb(),
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Restore the profile lighting of the selected device after commands that may have
+    /// taken it over, unless it is showing a flash or an overlay
+    fn release_rgb(&mut self, device_serial: Option<&DeviceSerial>) {
+        let lit = device_serial.is_some_and(|device_serial| {
+            self.flashes.contains_key(device_serial) || self.overlays.contains_key(device_serial)
+        });
+
+        if !lit {
+            self.backend.reset_rgb();
+        }
+    }
+
+    pub fn device_indices(&mut self) -> Result<DeviceIndices> {
+        let mut device_indices = DeviceIndices::new();
+        for device in self.devices()? {
+            let device_serial = DeviceSerial::from(&device);
+            let profile_index = self.profile_index(&device_serial)?;
+            device_indices.insert(device_serial, profile_index);
+        }
+
+        self.release_rgb(self.selected.clone().as_ref());
+
+        Ok(device_indices)
+    }
+
+    /// Read the active profile index of every device, leaving out devices that don't respond
+    ///
+    /// Unlike `device_indices` this leaves the lighting alone, so it can be polled.
+    pub fn profile_indices(&mut self) -> Result<DeviceIndices> {
+        let mut profile_indices = DeviceIndices::new();
+        for device in self.devices()? {
+            let device_serial = DeviceSerial::from(&device);
+            if let Ok(profile_index) = self.profile_index(&device_serial) {
+                profile_indices.insert(device_serial, profile_index);
+            }
+        }
+
+        Ok(profile_indices)
+    }
+
+    /// Apply the profile indices to the connected devices
+    ///
+    /// Negative profile indices and disconnected devices are skipped. Every device is
+    /// attempted even if one fails, the first error is returned afterwards.
+    pub fn set_device_indices(
+        &mut self,
+        device_indices: &DeviceIndices,
+        options: &SwitchOptions,
+    ) -> Result<()> {
+        self.ensure_scanned()?;
+
+        let mut result = Ok(());
+        for (device_serial, &profile_index) in device_indices {
+            if profile_index < 0 || !self.devices.contains_key(device_serial) {
+                continue;
+            }
+
+            let written = self.set_profile_index(device_serial, profile_index, options);
+            if result.is_ok() {
+                result = written;
+            }
+        }
+
+        self.release_rgb(self.selected.clone().as_ref());
+
+        result
+    }
+}
+
+/// Whether the error suggests the cached slots no longer match the connected devices
+const fn is_stale(error: &Error) -> bool {
+    matches!(
+        error,
+        Error::Timeout { .. }
+            | Error::SendFailed { .. }
+            | Error::InvalidResponse { .. }
+            | Error::ProtocolMismatch { .. }
+            | Error::UnexpectedCommand { .. }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Command,
+        LightingMode,
+        SimulatedBackend,
+        SimulatedCommand,
+        SimulatedDevice,
+        SIMULATED_PROFILE_COUNT,
+    };
+
+    const ACTIVATE_PROFILE: u8 = Command::ActivateProfile as u8;
+    const GET_SERIAL: u8 = Command::GetSerial as u8;
+
+    fn simulated(device_count: u8) -> (DeviceManager, SimulatedBackend) {
+        let backend = SimulatedBackend::new(device_count);
+        let manager = DeviceManager::new(Box::new(backend.clone()));
+
+        (manager, backend)
+    }
+
+    fn options(retries: u32) -> SwitchOptions {
+        SwitchOptions {
+            send_timeout_ms: 20,
+            retries,
+            ..Default::default()
+        }
+    }
+
+    fn device_serial(manager: &mut DeviceManager, slot: usize) -> DeviceSerial {
+        DeviceSerial::from(&manager.devices().unwrap()[slot])
+    }
+
+    fn activations(backend: &SimulatedBackend, device_index: u8) -> Vec<SimulatedCommand> {
+        backend
+            .state()
+            .lock()
+            .commands
+            .iter()
+            .filter(|command| command.device_index == device_index)
+            .filter(|command| command.command == ACTIVATE_PROFILE)
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn scans_simulated_devices() {
+        let (mut manager, _) = simulated(2);
+        let devices = manager.devices().unwrap();
+
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0].model_name, "Wooting 60HE");
+        assert_eq!(devices[1].model_name, "Wooting Two HE");
+        assert_eq!(devices[1].profile_slots(), Some(SIMULATED_PROFILE_COUNT));
+    }
+
+    #[test]
+    fn finds_no_devices() {
+        let (mut manager, _) = simulated(0);
+
+        assert!(manager.devices().unwrap().is_empty());
+        assert!(manager.device_indices().unwrap().is_empty());
+        assert!(matches!(manager.selected_or_first(), Err(Error::NoDevices)));
+    }
+
+    #[test]
+    fn skips_devices_that_dont_report_a_serial() {
+        let (mut manager, backend) = simulated(3);
+        backend.state().lock().devices[1].unresponsive = true;
+        let devices = manager.devices().unwrap();
+
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0].model_name, "Wooting 60HE");
+        assert_eq!(devices[1].model_name, "Wooting 80HE");
+
+        backend.state().lock().devices[1].unresponsive = false;
+        manager.rescan().unwrap();
+
+        assert_eq!(manager.devices().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn reads_capabilities_once_per_device() {
+        let (mut manager, backend) = simulated(2);
+        manager.rescan().unwrap();
+        backend.state().lock().devices[0].device.firmware_version = None;
+        manager.rescan().unwrap();
+
+        let version_reads = backend
+            .state()
+            .lock()
+            .commands
+            .iter()
+            .filter(|command| command.command == Command::GetVersion as u8)
+            .count();
+        assert_eq!(version_reads, 2);
+        assert!(manager.devices().unwrap()[0].firmware_version.is_some());
+
+        backend.state().lock().devices[0].connected = false;
+        manager.rescan().unwrap();
+        backend.state().lock().devices[0].connected = true;
+        manager.rescan().unwrap();
+        assert_eq!(manager.devices().unwrap()[0].firmware_version, None);
+    }
+
+    #[test]
+    fn switches_and_reads_back_the_profile() {
+        let (mut manager, backend) = simulated(2);
+        let device_serial = device_serial(&mut manager, 1);
+        manager
+            .set_profile_index(&device_serial, 2, &options(0))
+            .unwrap();
+
+        assert_eq!(manager.profile_index(&device_serial).unwrap(), 2);
+        assert_eq!(backend.state().lock().devices[0].profile_index, 0);
+        assert_eq!(
+            activations(&backend, 1),
+            [SimulatedCommand {
+                device_index: 1,
+                command:      ACTIVATE_PROFILE,
+                parameters:   [0, 0, 0, 2],
+            }]
+        );
+    }
+
+    /// Switch the first device to the third profile, returning the commands it was sent
+    fn switch_commands(lighting: LightingMode) -> Vec<Command> {
+        let (mut manager, backend) = simulated(1);
+        let device_serial = device_serial(&mut manager, 0);
+        let sent = backend.state().lock().commands.len();
+        let options = SwitchOptions {
+            lighting,
+            ..options(0)
+        };
+        manager
+            .set_profile_index(&device_serial, 2, &options)
+            .unwrap();
+
+        let commands = backend.state().lock().commands.clone();
+        commands
+            .iter()
+            .skip(sent)
+            .filter_map(|command| Command::from_repr(command.command))
+            .collect()
+    }
+
+    #[test]
+    fn sends_the_commands_of_each_lighting_mode() {
+        const PROFILE: [Command; 4] = [
+            Command::ActivateProfile,
+            Command::GetCurrentKeyboardProfileIndex,
+            Command::ReloadProfile,
+            Command::GetCurrentKeyboardProfileIndex,
+        ];
+        const LIGHTING: [Command; 3] = [
+            Command::WootDevResetAll,
+            Command::GetCurrentKeyboardProfileIndex,
+            Command::RefreshRgbColors,
+        ];
+
+        assert_eq!(switch_commands(LightingMode::ProfileOnly), PROFILE);
+        assert_eq!(switch_commands(LightingMode::LightingOnly), LIGHTING);
+        assert_eq!(
+            switch_commands(LightingMode::Both),
+            [&PROFILE[..], &LIGHTING[..]].concat()
+        );
+    }
+
+    #[test]
+    fn swaps_only_the_lighting_of_the_profile_slot() {
+        let (mut manager, backend) = simulated(1);
+        let device_serial = device_serial(&mut manager, 0);
+        let options = SwitchOptions {
+            lighting: LightingMode::LightingOnly,
+            ..options(0)
+        };
+        manager
+            .set_profile_index(&device_serial, 2, &options)
+            .unwrap();
+
+        assert_eq!(manager.profile_index(&device_serial).unwrap(), 0);
+        assert!(activations(&backend, 0).is_empty());
+    }
+
+    #[test]
+    fn retries_ignored_switches() {
+        let (mut manager, backend) = simulated(1);
+        let device_serial = device_serial(&mut manager, 0);
+        backend.state().lock().devices[0].busy = 2;
+        manager
+            .set_profile_index(&device_serial, 3, &options(2))
+            .unwrap();
+
+        assert_eq!(manager.profile_index(&device_serial).unwrap(), 3);
+        assert_eq!(activations(&backend, 0).len(), 3);
+    }
+
+    #[test]
+    fn fails_after_the_last_retry() {
+        let (mut manager, backend) = simulated(1);
+        let device_serial = device_serial(&mut manager, 0);
+        backend.state().lock().devices[0].busy = 5;
+        let result = manager.set_profile_index(&device_serial, 3, &options(1));
+
+        assert!(matches!(
+            result,
+            Err(Error::SwitchFailed {
+                expected: 3,
+                got:      Some(0),
+                attempts: 2,
+            })
+        ));
+        assert_eq!(activations(&backend, 0).len(), 2);
+    }
+
+    #[test]
+    fn caps_the_number_of_retries() {
+        let (mut manager, backend) = simulated(1);
+        let device_serial = device_serial(&mut manager, 0);
+        backend.state().lock().devices[0].busy = u32::MAX;
+        let result = manager.set_profile_index(&device_serial, 3, &options(u32::MAX));
+
+        assert!(matches!(
+            result,
+            Err(Error::SwitchFailed { attempts: 6, .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_profiles_out_of_range() {
+        let (mut manager, backend) = simulated(1);
+        let device_serial = device_serial(&mut manager, 0);
+        #[allow(clippy::cast_possible_wrap)]
+        let profile_index = SIMULATED_PROFILE_COUNT as ProfileIndex;
+        let result = manager.set_profile_index(&device_serial, profile_index, &options(0));
+
+        assert!(matches!(result, Err(Error::ProfileOutOfRange { .. })));
+        assert!(activations(&backend, 0).is_empty());
+    }
+
+    #[test]
+    fn skips_negative_indices_and_unknown_devices() {
+        let (mut manager, backend) = simulated(2);
+        let device_indices = DeviceIndices::from([
+            (device_serial(&mut manager, 0), 1),
+            (device_serial(&mut manager, 1), -1),
+            (DeviceSerial::default(), 2),
+        ]);
+        manager
+            .set_device_indices(&device_indices, &options(0))
+            .unwrap();
+
+        let profile_indices = backend
+            .state()
+            .lock()
+            .devices
+            .iter()
+            .map(|device| device.profile_index)
+            .collect::<Vec<_>>();
+        assert_eq!(profile_indices, [1, 0]);
+    }
+
+    #[test]
+    fn rescans_after_a_device_is_reconnected() {
+        let (mut manager, backend) = simulated(2);
+        let device_serial = device_serial(&mut manager, 1);
+        backend.state().lock().devices[1].connected = false;
+        assert!(manager.profile_index(&device_serial).is_err());
+
+        backend.state().lock().devices[1].connected = true;
+        assert_eq!(manager.profile_index(&device_serial).unwrap(), 0);
+    }
+
+    #[test]
+    fn refreshes_only_when_the_devices_change() {
+        let (mut manager, backend) = simulated(2);
+        manager.devices().unwrap();
+        let sent = backend.state().lock().commands.len();
+        manager.refresh().unwrap();
+        let commands = backend.state().lock().commands.clone();
+        assert!(commands
+            .iter()
+            .skip(sent)
+            .all(|command| command.command == GET_SERIAL));
+
+        backend.state().lock().devices[1].connected = false;
+        manager.refresh().unwrap();
+        assert_eq!(manager.devices().unwrap().len(), 1);
+
+        backend.state().lock().devices[1].connected = true;
+        manager.refresh().unwrap();
+        assert_eq!(manager.devices().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn refreshes_when_devices_swap_slots() {
+        let (mut manager, backend) = simulated(2);
+        let first = device_serial(&mut manager, 0);
+        let second = device_serial(&mut manager, 1);
+        backend.state().lock().devices.swap(0, 1);
+        manager.refresh().unwrap();
+
+        assert_eq!(device_serial(&mut manager, 0), second);
+        assert_eq!(device_serial(&mut manager, 1), first);
+
+        manager.set_profile_index(&first, 2, &options(0)).unwrap();
+        assert_eq!(activations(&backend, 1).len(), 1);
+        assert!(activations(&backend, 0).is_empty());
+    }
+
+    #[test]
+    fn refreshes_when_a_device_is_replaced() {
+        let (mut manager, backend) = simulated(2);
+        let replaced = device_serial(&mut manager, 1);
+        backend.state().lock().devices[1] = SimulatedDevice::new(2);
+        manager.refresh().unwrap();
+
+        let devices = manager.devices().unwrap();
+        assert_eq!(devices.len(), 2);
+        assert!(devices
+            .iter()
+            .all(|device| DeviceSerial::from(device) != replaced));
+    }
+
+    #[test]
+    fn refreshes_when_a_device_stops_answering() {
+        let (mut manager, backend) = simulated(2);
+        manager.devices().unwrap();
+        backend.state().lock().devices[1].unresponsive = true;
+        manager.refresh().unwrap();
+        assert_eq!(manager.devices().unwrap().len(), 1);
+
+        backend.state().lock().devices[1].unresponsive = false;
+        manager.refresh().unwrap();
+        assert_eq!(manager.devices().unwrap().len(), 2);
+    }
+
+    fn key_colors(backend: &SimulatedBackend, device_index: usize) -> Vec<KeyColor> {
+        backend.state().lock().devices[device_index]
+            .key_colors
+            .clone()
+    }
+
+    #[test]
+    fn ends_flashes_once_their_duration_has_passed() {
+        let (mut manager, backend) = simulated(2);
+        let device_serial = device_serial(&mut manager, 1);
+        let flash = FlashOptions::default();
+        manager.flash(&device_serial, 2, &flash);
+        assert_eq!(key_colors(&backend, 1), flash.key_colors(2));
+
+        let ends = manager.next_flash_end().unwrap();
+        manager.end_flashes(Instant::now());
+        assert_eq!(key_colors(&backend, 1), flash.key_colors(2));
+
+        manager.end_flashes(ends);
+        assert!(key_colors(&backend, 1).is_empty());
+        assert_eq!(manager.next_flash_end(), None);
+    }
+
+    #[test]
+    fn ends_flashes_on_the_overlay() {
+        let (mut manager, backend) = simulated(1);
+        let device_serial = device_serial(&mut manager, 0);
+        let overlay = FlashOptions::default().key_colors(0);
+        manager
+            .set_overlay(&device_serial, overlay.clone())
+            .unwrap();
+
+        manager.flash(&device_serial, 3, &FlashOptions::default());
+        assert_ne!(key_colors(&backend, 0), overlay);
+
+        manager.end_flashes(manager.next_flash_end().unwrap());
+        assert_eq!(key_colors(&backend, 0), overlay);
+    }
+
+    #[test]
+    fn extends_a_flash_still_showing() {
+        let (mut manager, _) = simulated(1);
+        let device_serial = device_serial(&mut manager, 0);
+        manager.flash(&device_serial, 1, &FlashOptions::default());
+        let first_end = manager.next_flash_end().unwrap();
+
+        let flash = FlashOptions {
+            duration_ms: 60_000,
+            ..Default::default()
+        };
+        manager.flash(&device_serial, 2, &flash);
+        manager.end_flashes(first_end);
+
+        assert!(manager.next_flash_end().unwrap() > first_end);
+    }
+
+    #[test]
+    fn keeps_only_the_latest_commands() {
+        let (mut manager, backend) = simulated(1);
+        let device_serial = device_serial(&mut manager, 0);
+        for _ in 0..2000 {
+            manager.profile_index(&device_serial).unwrap();
+        }
+
+        assert_eq!(backend.state().lock().commands.len(), 1024);
+    }
+}
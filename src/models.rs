@@ -0,0 +1,68 @@
+use std::sync::LazyLock;
+
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+
+use crate::Device;
+
+/* Constants */
+
+// These must match exactly what the Wooting firmware reports (Wootility var KeyboardType)
+// The table is matched by model name only, product IDs can be added through `set_models`
+static DEFAULT_MODELS: LazyLock<Vec<Model>> = LazyLock::new(|| {
+    serde_json::from_str(include_str!("models.json")).expect("Invalid default model table")
+});
+
+static MODELS: RwLock<Vec<Model>> = RwLock::new(Vec::new());
+
+/* Structures */
+
+/// Maps a keyboard to the keyboard type Wootility uses in its device IDs
+#[derive(Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(default)]
+pub struct Model {
+    /// Model name reported by the USB meta, e.g. "Wooting 60HE (ARM)"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model_name:     Option<String>,
+    /// USB product ID, only reported by the hid backend
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usb_product_id: Option<u16>,
+    pub keyboard_type:  u32,
+}
+
+/* Implementations */
+
+impl Model {
+    /// Whether the entry describes the device, the product ID takes precedence
+    #[must_use]
+    pub fn matches(&self, device: &Device) -> bool {
+        match (self.usb_product_id, device.usb_product_id) {
+            (Some(expected), Some(got)) => expected == got,
+            _ => self.model_name.as_ref() == Some(&device.model_name),
+        }
+    }
+}
+
+/* Functions */
+
+/// The model table shipped with the library
+#[must_use]
+pub fn default_models() -> &'static [Model] {
+    &DEFAULT_MODELS
+}
+
+/// Replace the models checked before the default table
+pub fn set_models(models: Vec<Model>) {
+    *MODELS.write() = models;
+}
+
+/// The Wootility keyboard type of the device, if its model is known
+#[must_use]
+pub fn keyboard_type(device: &Device) -> Option<u32> {
+    let models = MODELS.read();
+    models
+        .iter()
+        .chain(DEFAULT_MODELS.iter())
+        .find(|model| model.matches(device))
+        .map(|model| model.keyboard_type)
+}
@@ -0,0 +1,474 @@
+use derive_more::{Display, Error};
+
+/* Constants */
+
+// https://protobuf.dev/programming-guides/encoding/#structure
+const WIRE_VARINT: u8 = 0;
+const WIRE_I64: u8 = 1;
+const WIRE_LEN: u8 = 2;
+const WIRE_SGROUP: u8 = 3;
+const WIRE_EGROUP: u8 = 4;
+const WIRE_I32: u8 = 5;
+const MAX_VARINT_BYTES: usize = 10;
+
+/* Structures */
+
+#[derive(Clone, Debug, Display, Eq, Error, PartialEq)]
+pub enum DecodeError {
+    #[display("Buffer truncated at offset {offset}, needed {needed} more byte(s)")]
+    Truncated { offset: usize, needed: usize },
+    #[display("Varint at offset {offset} is longer than 10 bytes")]
+    VarintTooLong { offset: usize },
+    #[display("Field {field} value {value} is out of range")]
+    ValueTooLarge { field: u32, value: u64 },
+    #[display("Field {field} has invalid wire type {wire_type}")]
+    InvalidWireType { field: u32, wire_type: u8 },
+    #[display("Field {field} has an unmatched end group")]
+    UnmatchedGroup { field: u32 },
+}
+
+/// A field key, the field number and wire type
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Key {
+    pub field:     u32,
+    pub wire_type: u8,
+}
+
+/// Bounds checked reader over a protobuf encoded buffer
+#[derive(Clone, Debug)]
+pub struct Reader<'a> {
+    buffer: &'a [u8],
+    offset: usize,
+}
+
+/* Implementations */
+
+impl<'a> Reader<'a> {
+    #[must_use]
+    pub const fn new(buffer: &'a [u8]) -> Self {
+        Self { buffer, offset: 0 }
+    }
+
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.offset >= self.buffer.len()
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], DecodeError> {
+        let remaining = self.buffer.len() - self.offset;
+        if length > remaining {
+            return Err(DecodeError::Truncated {
+                offset: self.offset,
+                needed: length - remaining,
+            });
+        }
+
+        let bytes = &self.buffer[self.offset..self.offset + length];
+        self.offset += length;
+
+        Ok(bytes)
+    }
+
+    pub fn read_varint(&mut self) -> Result<u64, DecodeError> {
+        let start = self.offset;
+        let mut result: u64 = 0;
+
+        for i in 0..MAX_VARINT_BYTES {
+            let byte = self.take(1)?[0];
+            result |= u64::from(byte & 0x7F) << (i * 7);
+
+            if byte & 0x80 == 0 {
+                return Ok(result);
+            }
+        }
+
+        Err(DecodeError::VarintTooLong { offset: start })
+    }
+
+    pub fn read_key(&mut self) -> Result<Key, DecodeError> {
+        let key = self.read_varint()?;
+
+        #[allow(clippy::cast_possible_truncation)]
+        Ok(Key {
+            field:     (key >> 3) as u32,
+            wire_type: (key & 7) as u8,
+        })
+    }
+
+    /// Read a varint field value that must fit in 32 bits
+    pub fn read_u32(&mut self, key: Key) -> Result<u32, DecodeError> {
+        if key.wire_type != WIRE_VARINT {
+            return Err(DecodeError::InvalidWireType {
+                field:     key.field,
+                wire_type: key.wire_type,
+            });
+        }
+
+        let value = self.read_varint()?;
+        u32::try_from(value).map_err(|_| DecodeError::ValueTooLarge {
+            field: key.field,
+            value,
+        })
+    }
+
+    /// Read a varint field value that must fit in a byte
+    pub fn read_u8(&mut self, key: Key) -> Result<u8, DecodeError> {
+        let value = self.read_u32(key)?;
+        u8::try_from(value).map_err(|_| DecodeError::ValueTooLarge {
+            field: key.field,
+            value: u64::from(value),
+        })
+    }
+
+    /// Skip the value of a field, including any nested fields of a group
+    pub fn skip(&mut self, key: Key) -> Result<(), DecodeError> {
+        match key.wire_type {
+            WIRE_VARINT => {
+                self.read_varint()?;
+            }
+            WIRE_I64 => {
+                self.take(8)?;
+            }
+            WIRE_LEN => {
+                let length = self.read_varint()?;
+                let length = usize::try_from(length).unwrap_or(usize::MAX);
+                self.take(length)?;
+            }
+            WIRE_SGROUP => loop {
+                let nested = self.read_key()?;
+                if nested.wire_type == WIRE_EGROUP {
+                    if nested.field != key.field {
+                        return Err(DecodeError::UnmatchedGroup {
+                            field: nested.field,
+                        });
+                    }
+
+                    break;
+                }
+
+                self.skip(nested)?;
+            },
+            WIRE_EGROUP => {
+                return Err(DecodeError::UnmatchedGroup { field: key.field });
+            }
+            WIRE_I32 => {
+                self.take(4)?;
+            }
+            wire_type => {
+                return Err(DecodeError::InvalidWireType {
+                    field: key.field,
+                    wire_type,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Device, DeviceID, DeviceSerial, Error, Stage};
+
+    /// `GET_SERIAL` payload of the keyboard with the serial A02B2106W031H00418
+    const SERIAL_PAYLOAD: [u8; 15] = [
+        0x08, 0x02, // Supplier 2
+        0x10, 0x15, // Year 21
+        0x18, 0x06, // Week 6
+        0x20, 0x03, // Product 3
+        0x28, 0x01, // Revision 1
+        0x30, 0xA2, 0x03, // Product ID 418
+        0x38, 0x00, // Stage H
+    ];
+
+    /// Offsets between the fields of `SERIAL_PAYLOAD`
+    const FIELD_BOUNDARIES: [usize; 8] = [0, 2, 4, 6, 8, 10, 13, 15];
+
+    /// A `GET_SERIAL` payload for each model of the built-in table and its serial, covering the
+    /// optional PCB design, minor revision and variant fields and multi-byte product IDs
+    const MODEL_PAYLOADS: [(&str, &str, &[u8]); 11] = [
+        (
+            "Wooting One",
+            "A01B1822W011H00421",
+            &[
+                0x08, 0x01, 0x10, 0x12, 0x18, 0x16, 0x20, 0x01, 0x28, 0x01, 0x30, 0xA5, 0x03, 0x38,
+                0x00,
+            ],
+        ),
+        (
+            "Wooting Two",
+            "A01B1935W021H01337",
+            &[
+                0x08, 0x01, 0x10, 0x13, 0x18, 0x23, 0x20, 0x02, 0x28, 0x01, 0x30, 0xB9, 0x0A, 0x38,
+                0x00,
+            ],
+        ),
+        ("Wooting Two LE", "A02B2106W031H00418", &SERIAL_PAYLOAD),
+        (
+            "Wooting Two HE",
+            "A02B2145W042H10532",
+            &[
+                0x08, 0x02, 0x10, 0x15, 0x18, 0x2D, 0x20, 0x04, 0x28, 0x02, 0x30, 0xA4, 0x52, 0x38,
+                0x00,
+            ],
+        ),
+        (
+            "Wooting 60HE",
+            "A02B2208W052H23871",
+            &[
+                0x08, 0x02, 0x10, 0x16, 0x18, 0x08, 0x20, 0x05, 0x28, 0x02, 0x30, 0xBF, 0xBA, 0x01,
+                0x38, 0x00,
+            ],
+        ),
+        (
+            "Wooting 60HE (ARM)",
+            "A02B2331W06T013H04410",
+            &[
+                0x08, 0x02, 0x10, 0x17, 0x18, 0x1F, 0x20, 0x06, 0x28, 0x03, 0x30, 0xBA, 0x22, 0x38,
+                0x00, 0x50, 0x01,
+            ],
+        ),
+        (
+            "Wooting Two HE (ARM)",
+            "A02B2340W07T023H00815",
+            &[
+                0x08, 0x02, 0x10, 0x17, 0x18, 0x28, 0x20, 0x07, 0x28, 0x03, 0x30, 0xAF, 0x06, 0x38,
+                0x00, 0x50, 0x02,
+            ],
+        ),
+        (
+            "Wooting UwU",
+            "A02B2312W081H01984",
+            &[
+                0x08, 0x02, 0x10, 0x17, 0x18, 0x0C, 0x20, 0x08, 0x28, 0x01, 0x30, 0xC0, 0x0F, 0x38,
+                0x00,
+            ],
+        ),
+        (
+            "Wooting UwU RGB",
+            "A02B2350W091S01H00207",
+            &[
+                0x08, 0x02, 0x10, 0x17, 0x18, 0x32, 0x20, 0x09, 0x28, 0x01, 0x30, 0xCF, 0x01, 0x38,
+                0x00, 0x48, 0x01,
+            ],
+        ),
+        (
+            "Wooting 60HE+",
+            "A02B2418W0A101P31415",
+            &[
+                0x08, 0x02, 0x10, 0x18, 0x18, 0x12, 0x20, 0x0A, 0x28, 0x01, 0x30, 0xB7, 0xF5, 0x01,
+                0x38, 0x01, 0x58, 0x01,
+            ],
+        ),
+        (
+            "Wooting 80HE",
+            "A02B2436W0BT01200H00042",
+            &[
+                0x08, 0x02, 0x10, 0x18, 0x18, 0x24, 0x20, 0x0B, 0x28, 0x02, 0x30, 0x2A, 0x38, 0x00,
+                0x50, 0x01, 0x58, 0x00,
+            ],
+        ),
+    ];
+
+    fn serial_response(payload: &[u8]) -> Vec<u8> {
+        let mut response = vec![0xD0, 0xDA, 0x03, 0x00];
+        response.push(u8::try_from(payload.len()).unwrap());
+        response.extend_from_slice(payload);
+        response.resize(256, 0);
+
+        response
+    }
+
+    fn decode(payload: &[u8]) -> Result<Device, DecodeError> {
+        Device::decode_serial(payload)
+    }
+
+    #[test]
+    fn decodes_a_serial_response() {
+        let device = Device::try_from(serial_response(&SERIAL_PAYLOAD)).unwrap();
+
+        assert_eq!(device.supplier, 2);
+        assert_eq!(device.year, 21);
+        assert_eq!(device.week, 6);
+        assert_eq!(device.product, 3);
+        assert_eq!(device.revision, 1);
+        assert_eq!(device.product_id, 418);
+        assert_eq!(device.stage, Stage::H);
+        assert_eq!(
+            DeviceSerial::from(&device).to_string(),
+            "A02B2106W031H00418"
+        );
+    }
+
+    #[test]
+    fn decodes_a_serial_payload_of_every_model() {
+        for (model_name, serial, payload) in MODEL_PAYLOADS {
+            let mut device = Device::try_from(serial_response(payload)).unwrap();
+            assert_eq!(DeviceSerial::from(&device).to_string(), serial);
+
+            device.model_name = model_name.to_owned();
+            assert!(DeviceID::try_from(&device).is_ok(), "{model_name}");
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_payloads_of_every_model() {
+        for (model_name, _, payload) in MODEL_PAYLOADS {
+            for length in 0..payload.len() {
+                let result = decode(&payload[..length]);
+                assert!(
+                    matches!(result, Ok(_) | Err(DecodeError::Truncated { .. })),
+                    "{model_name} length {length}: {result:?}"
+                );
+
+                let result = Device::try_from(serial_response(payload)[..5 + length].to_vec());
+                assert!(
+                    matches!(result, Err(Error::InvalidResponse { .. })),
+                    "{model_name} length {length}: {result:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn survives_malformed_payloads_of_every_model() {
+        for (model_name, _, payload) in MODEL_PAYLOADS {
+            let mut response = serial_response(payload);
+            for offset in 4..5 + payload.len() {
+                let original = response[offset];
+                let bytes = (0..8).map(|bit| original ^ (1 << bit));
+                for byte in [0x00, 0x7F, 0x80, 0xFF].into_iter().chain(bytes) {
+                    response[offset] = byte;
+
+                    // Any decoded device or error will do, as long as nothing panics
+                    let result = std::panic::catch_unwind(|| {
+                        let _ = decode(&response[5..]);
+                        let _ = Device::try_from(response.clone());
+                    });
+                    assert!(
+                        result.is_ok(),
+                        "{model_name} byte {offset} set to {byte:#04X}"
+                    );
+                }
+
+                response[offset] = original;
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_payloads() {
+        for length in 0..SERIAL_PAYLOAD.len() {
+            let result = decode(&SERIAL_PAYLOAD[..length]);
+            if FIELD_BOUNDARIES.contains(&length) {
+                assert!(result.is_ok(), "length {length}: {result:?}");
+            } else {
+                assert_eq!(
+                    result,
+                    Err(DecodeError::Truncated {
+                        offset: length,
+                        needed: 1,
+                    }),
+                    "length {length}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_responses() {
+        let response = serial_response(&SERIAL_PAYLOAD);
+        for length in 0..5 + SERIAL_PAYLOAD.len() {
+            let result = Device::try_from(response[..length].to_vec());
+            assert!(
+                matches!(result, Err(Error::InvalidResponse { .. })),
+                "length {length}: {result:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_varints() {
+        let mut too_long = vec![0x08];
+        too_long.extend([0xFF; MAX_VARINT_BYTES]);
+        assert_eq!(
+            decode(&too_long),
+            Err(DecodeError::VarintTooLong { offset: 1 })
+        );
+
+        assert_eq!(
+            decode(&[0x08, 0x80, 0x80, 0x80, 0x80, 0x10]),
+            Err(DecodeError::ValueTooLarge {
+                field: 1,
+                value: 1 << 32,
+            })
+        );
+
+        // The supplier and product are written as two hex digits in the serial
+        assert_eq!(
+            decode(&[0x20, 0x80, 0x02]),
+            Err(DecodeError::ValueTooLarge {
+                field: 4,
+                value: 0x100,
+            })
+        );
+
+        assert_eq!(
+            decode(&[0x0D, 0x02, 0x00, 0x00, 0x00]),
+            Err(DecodeError::InvalidWireType {
+                field:     1,
+                wire_type: WIRE_I32,
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_lengths() {
+        assert_eq!(
+            decode(&[0x42, 0x05, 0x01]),
+            Err(DecodeError::Truncated {
+                offset: 2,
+                needed: 4,
+            })
+        );
+
+        let result = decode(&[0x42, 0xFF, 0xFF, 0xFF, 0xFF, 0x0F]);
+        assert!(
+            matches!(result, Err(DecodeError::Truncated { offset: 6, .. })),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_wire_types_and_groups() {
+        assert_eq!(
+            decode(&[0x46]),
+            Err(DecodeError::InvalidWireType {
+                field:     8,
+                wire_type: 6,
+            })
+        );
+        assert_eq!(
+            decode(&[0x44]),
+            Err(DecodeError::UnmatchedGroup { field: 8 })
+        );
+        assert_eq!(
+            decode(&[0x43, 0x08, 0x01, 0x54]),
+            Err(DecodeError::UnmatchedGroup { field: 10 })
+        );
+    }
+
+    #[test]
+    fn skips_unknown_fields() {
+        let mut payload = vec![
+            0x41, 1, 2, 3, 4, 5, 6, 7, 8, // Field 8, 64-bit
+            0x72, 0x02, 0xAB, 0xCD, // Field 14, length delimited
+            0x63, 0x08, 0x01, 0x64, // Field 12, group
+            0x6D, 1, 2, 3, 4, // Field 13, 32-bit
+        ];
+        payload.extend_from_slice(&SERIAL_PAYLOAD);
+
+        assert_eq!(decode(&payload), decode(&SERIAL_PAYLOAD));
+    }
+}
@@ -0,0 +1,492 @@
+use derive_more::Display;
+use strum::FromRepr;
+
+use crate::{Device, DeviceBackend, Error, FirmwareVersion, ProfileIndex, Result};
+
+/* Constants */
+
+// Reverse Engineered from Wootility
+// https://gist.github.com/BigBrainAFK/0ba454a1efb43f7cb6301cda8838f432
+pub const MAGIC_WORD_V2: u16 = 0xDAD0;
+pub const MAGIC_WORD_V3: u16 = 0xDAD1;
+
+/* Traits */
+
+/// A command sent to the keyboard
+pub trait Request {
+    const COMMAND: Command;
+
+    /// The four parameter bytes sent with the command
+    fn parameters(&self) -> [u8; 4] {
+        [0; 4]
+    }
+}
+
+/// A command the keyboard answers with a response frame
+pub trait Query: Request {
+    type Response;
+
+    /// Decode the payload of the response frame
+    fn decode(payload: &[u8]) -> Result<Self::Response>;
+}
+
+/* Structures */
+
+/// Feature report command IDs
+#[derive(Clone, Copy, Debug, Display, Eq, FromRepr, Hash, PartialEq)]
+#[repr(u8)]
+pub enum Command {
+    GetVersion = 1,
+    GetSerial  = 3,
+    GetRgbProfileCount = 4,
+    ReloadProfile = 7,
+    GetDigitalProfilesCount = 9,
+    GetAnalogProfilesCount = 10,
+    GetCurrentKeyboardProfileIndex = 11,
+    ActivateProfile = 23,
+    RefreshRgbColors = 29,
+    WootDevResetAll = 32,
+}
+
+/// How a keyboard frames its responses
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct FrameLayout {
+    /// Responses are prefixed with a report ID and use the V3 magic word
+    pub multi_report: bool,
+    /// Responses have an extra header byte before the length
+    pub v2_interface: bool,
+}
+
+/// A response frame with a valid header
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Frame<'a> {
+    pub command: u8,
+    pub payload: &'a [u8],
+}
+
+/// Read the firmware version of the keyboard
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GetVersion;
+
+/// Read the serial fields of the keyboard, the model name is left empty
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GetSerial;
+
+/// Read the number of profile slots, including the digital profile
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GetRgbProfileCount;
+
+/// Read the number of digital profile slots
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GetDigitalProfilesCount;
+
+/// Read the number of analog profile slots
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GetAnalogProfilesCount;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GetCurrentKeyboardProfileIndex;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ActivateProfile {
+    pub profile_index: u8,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ReloadProfile {
+    pub profile_index: u8,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RefreshRgbColors {
+    pub profile_index: u8,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WootDevResetAll;
+
+/* Implementations */
+
+impl FrameLayout {
+    /// A V2 interface keyboard using standard reports
+    pub const STANDARD: Self = Self {
+        multi_report: false,
+        v2_interface: true,
+    };
+
+    /// The layout used by the selected keyboard
+    #[must_use]
+    pub fn of(backend: &dyn DeviceBackend) -> Self {
+        Self {
+            multi_report: backend.uses_multi_report(),
+            v2_interface: backend.uses_v2_interface(),
+        }
+    }
+
+    #[must_use]
+    pub const fn magic_word(self) -> u16 {
+        if self.multi_report {
+            MAGIC_WORD_V3
+        } else {
+            MAGIC_WORD_V2
+        }
+    }
+
+    /* Response Bytes (standard reports, V2 interface)
+     * 0-1 Magic Word
+     * 2   Command
+     * 3   Unknown (absent on the V1 interface, shifting the following bytes by -1)
+     * 4   Length
+     * 5-L Buffer
+     *
+     * Multi-report responses are prefixed with a report ID, shifting the bytes above
+     * by +1, and have an unknown byte between the length and the buffer.
+     */
+    fn length_offset(self) -> usize {
+        usize::from(self.multi_report) + if self.v2_interface { 4 } else { 3 }
+    }
+
+    fn payload_offset(self) -> usize {
+        self.length_offset() + 1 + usize::from(self.multi_report)
+    }
+}
+
+impl<'a> Frame<'a> {
+    /// Check the header of a response and locate its payload
+    pub fn parse(buffer: &'a [u8], layout: FrameLayout) -> Result<Self> {
+        let header_size = layout.payload_offset();
+        if buffer.len() < header_size {
+            return Err(Error::InvalidResponse {
+                expected: header_size,
+                got:      buffer.len(),
+            });
+        }
+
+        let magic_offset = usize::from(layout.multi_report);
+        let magic_word = u16::from_le_bytes([buffer[magic_offset], buffer[magic_offset + 1]]);
+        if magic_word != layout.magic_word() {
+            return Err(Error::ProtocolMismatch {
+                expected: layout.magic_word(),
+                got:      magic_word,
+            });
+        }
+
+        let length = usize::from(buffer[layout.length_offset()]);
+        let payload =
+            buffer
+                .get(header_size..header_size + length)
+                .ok_or(Error::InvalidResponse {
+                    expected: header_size + length,
+                    got:      buffer.len(),
+                })?;
+
+        Ok(Self {
+            command: buffer[magic_offset + 2],
+            payload,
+        })
+    }
+
+    /// Encode a response, padded to the response size
+    #[must_use]
+    pub fn encode(&self, layout: FrameLayout, response_size: usize) -> Vec<u8> {
+        let mut response = vec![0; layout.payload_offset()];
+        let magic_offset = usize::from(layout.multi_report);
+        response[magic_offset..magic_offset + 2]
+            .copy_from_slice(&layout.magic_word().to_le_bytes());
+        response[magic_offset + 2] = self.command;
+
+        #[allow(clippy::cast_possible_truncation)] // Payloads fit in a single report
+        let length = self.payload.len() as u8;
+        response[layout.length_offset()] = length;
+        response.extend_from_slice(self.payload);
+        response.resize(response_size.max(response.len()), 0);
+
+        response
+    }
+
+    /// Check the frame answers the command
+    pub const fn expect(self, command: Command) -> Result<Self> {
+        if self.command == command as u8 {
+            Ok(self)
+        } else {
+            Err(Error::UnexpectedCommand {
+                expected: command,
+                got:      self.command,
+            })
+        }
+    }
+}
+
+impl Request for GetVersion {
+    const COMMAND: Command = Command::GetVersion;
+}
+
+impl Query for GetVersion {
+    type Response = FirmwareVersion;
+
+    fn decode(payload: &[u8]) -> Result<Self::Response> {
+        let [major, minor, patch, ..] = *payload else {
+            return Err(Error::InvalidResponse {
+                expected: 3,
+                got:      payload.len(),
+            });
+        };
+
+        Ok(FirmwareVersion {
+            major,
+            minor,
+            patch,
+        })
+    }
+}
+
+impl Request for GetSerial {
+    const COMMAND: Command = Command::GetSerial;
+
+    fn parameters(&self) -> [u8; 4] {
+        [0, 0, 0, 2]
+    }
+}
+
+impl Query for GetSerial {
+    type Response = Device;
+
+    fn decode(payload: &[u8]) -> Result<Self::Response> {
+        Ok(Device::decode_serial(payload)?)
+    }
+}
+
+impl Request for GetRgbProfileCount {
+    const COMMAND: Command = Command::GetRgbProfileCount;
+}
+
+impl Query for GetRgbProfileCount {
+    type Response = u8;
+
+    fn decode(payload: &[u8]) -> Result<Self::Response> {
+        decode_count(payload)
+    }
+}
+
+impl Request for GetDigitalProfilesCount {
+    const COMMAND: Command = Command::GetDigitalProfilesCount;
+}
+
+impl Query for GetDigitalProfilesCount {
+    type Response = u8;
+
+    fn decode(payload: &[u8]) -> Result<Self::Response> {
+        decode_count(payload)
+    }
+}
+
+impl Request for GetAnalogProfilesCount {
+    const COMMAND: Command = Command::GetAnalogProfilesCount;
+}
+
+impl Query for GetAnalogProfilesCount {
+    type Response = u8;
+
+    fn decode(payload: &[u8]) -> Result<Self::Response> {
+        decode_count(payload)
+    }
+}
+
+impl Request for GetCurrentKeyboardProfileIndex {
+    const COMMAND: Command = Command::GetCurrentKeyboardProfileIndex;
+}
+
+impl Query for GetCurrentKeyboardProfileIndex {
+    type Response = ProfileIndex;
+
+    fn decode(payload: &[u8]) -> Result<Self::Response> {
+        let profile_index = *payload.first().ok_or(Error::InvalidResponse {
+            expected: 1,
+            got:      0,
+        })?;
+
+        #[allow(clippy::cast_possible_wrap)]
+        ProfileIndex::try_from(profile_index).map_err(|_| Error::InvalidProfileIndex {
+            profile_index: profile_index as ProfileIndex,
+        })
+    }
+}
+
+impl Request for ActivateProfile {
+    const COMMAND: Command = Command::ActivateProfile;
+
+    fn parameters(&self) -> [u8; 4] {
+        [0, 0, 0, self.profile_index]
+    }
+}
+
+impl Request for ReloadProfile {
+    const COMMAND: Command = Command::ReloadProfile;
+
+    fn parameters(&self) -> [u8; 4] {
+        [0, 0, 0, self.profile_index]
+    }
+}
+
+impl Request for RefreshRgbColors {
+    const COMMAND: Command = Command::RefreshRgbColors;
+
+    fn parameters(&self) -> [u8; 4] {
+        [0, 0, 0, self.profile_index]
+    }
+}
+
+impl Request for WootDevResetAll {
+    const COMMAND: Command = Command::WootDevResetAll;
+}
+
+/* Functions */
+
+/// Send a command to the selected keyboard without waiting for a response
+pub fn send<R: Request>(backend: &mut dyn DeviceBackend, request: &R) -> Result<()> {
+    if backend.send_feature(R::COMMAND as u8, request.parameters()) {
+        Ok(())
+    } else {
+        Err(Error::SendFailed {
+            command: R::COMMAND,
+        })
+    }
+}
+
+/// Send a command to the selected keyboard and decode its response
+pub fn query<Q: Query>(backend: &mut dyn DeviceBackend, query: &Q) -> Result<Q::Response> {
+    let response_size = backend.response_size();
+    let mut buffer = vec![0u8; response_size];
+    let response =
+        backend.send_feature_with_response(&mut buffer, Q::COMMAND as u8, query.parameters());
+
+    match response {
+        Some(got) if got == response_size => {}
+        Some(got) => {
+            return Err(Error::InvalidResponse {
+                expected: response_size,
+                got,
+            });
+        }
+        None => {
+            return Err(Error::Timeout {
+                command: Q::COMMAND,
+            });
+        }
+    }
+
+    let frame = Frame::parse(&buffer, FrameLayout::of(backend))?.expect(Q::COMMAND)?;
+    Q::decode(frame.payload)
+}
+
+/* Helpers */
+
+fn decode_count(payload: &[u8]) -> Result<u8> {
+    payload.first().copied().ok_or(Error::InvalidResponse {
+        expected: 1,
+        got:      0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const V1_INTERFACE: FrameLayout = FrameLayout {
+        multi_report: false,
+        v2_interface: false,
+    };
+    const MULTI_REPORT: FrameLayout = FrameLayout {
+        multi_report: true,
+        v2_interface: true,
+    };
+    const PAYLOAD: [u8; 3] = [2, 11, 4];
+
+    fn version_frame() -> Frame<'static> {
+        Frame {
+            command: Command::GetVersion as u8,
+            payload: &PAYLOAD,
+        }
+    }
+
+    #[test]
+    fn round_trips_every_layout() {
+        for layout in [FrameLayout::STANDARD, V1_INTERFACE, MULTI_REPORT] {
+            let response = version_frame().encode(layout, 64);
+            assert_eq!(response.len(), 64);
+            assert_eq!(
+                Frame::parse(&response, layout).unwrap(),
+                version_frame(),
+                "{layout:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn encodes_the_header_of_every_layout() {
+        let command = Command::GetVersion as u8;
+        assert_eq!(
+            version_frame().encode(FrameLayout::STANDARD, 0),
+            [0xD0, 0xDA, command, 0, 3, 2, 11, 4]
+        );
+        assert_eq!(
+            version_frame().encode(V1_INTERFACE, 0),
+            [0xD0, 0xDA, command, 3, 2, 11, 4]
+        );
+        assert_eq!(
+            version_frame().encode(MULTI_REPORT, 0),
+            [0, 0xD1, 0xDA, command, 0, 3, 0, 2, 11, 4]
+        );
+    }
+
+    #[test]
+    fn rejects_bad_magic_words() {
+        let response = version_frame().encode(FrameLayout::STANDARD, 64);
+        assert!(matches!(
+            Frame::parse(&response, MULTI_REPORT),
+            Err(Error::ProtocolMismatch {
+                expected: MAGIC_WORD_V3,
+                ..
+            })
+        ));
+
+        let mut response = response;
+        response[1] = 0xDB;
+        assert!(matches!(
+            Frame::parse(&response, FrameLayout::STANDARD),
+            Err(Error::ProtocolMismatch {
+                expected: MAGIC_WORD_V2,
+                got:      0xDBD0,
+            })
+        ));
+    }
+
+    #[test]
+    fn rejects_responses_to_other_commands() {
+        let response = version_frame().encode(FrameLayout::STANDARD, 64);
+        let frame = Frame::parse(&response, FrameLayout::STANDARD).unwrap();
+
+        assert!(frame.expect(Command::GetVersion).is_ok());
+        assert!(matches!(
+            frame.expect(Command::GetSerial),
+            Err(Error::UnexpectedCommand {
+                expected: Command::GetSerial,
+                got:      1,
+            })
+        ));
+    }
+
+    #[test]
+    fn rejects_truncated_payloads() {
+        for layout in [FrameLayout::STANDARD, V1_INTERFACE, MULTI_REPORT] {
+            let response = version_frame().encode(layout, 0);
+            for length in 0..response.len() {
+                let result = Frame::parse(&response[..length], layout);
+                assert!(
+                    matches!(result, Err(Error::InvalidResponse { got, .. }) if got == length),
+                    "{layout:?} length {length}: {result:?}"
+                );
+            }
+        }
+    }
+}
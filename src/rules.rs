@@ -0,0 +1,1416 @@
+use std::{
+    cmp::Reverse,
+    fmt,
+    time::{Duration, Instant},
+};
+
+use derive_more::Display;
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+use wooting_profile_switcher::{DeviceSerial, LightingMode, ProfileIndex};
+
+use crate::{
+    config::{Config, MatchGroup, MatchMode, MatchStrategy, Rule},
+    ActiveMatchInfo,
+};
+
+/* Constants */
+
+/// Config names of the match fields, in the order of `Conditions::match_fields`
+pub const MATCH_FIELDS: [&str; 4] = [
+    "match_app_name",
+    "match_bin_name",
+    "match_bin_path",
+    "match_win_name",
+];
+
+/// Regexes that match any value, as far as the linter is concerned
+const MATCH_ANYTHING: [&str; 5] = ["", ".*", "^.*", ".*$", "^.*$"];
+
+/* Structures */
+
+/// How a match pattern is interpreted
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+pub enum PatternKind {
+    /// Unprefixed patterns of older configs, tried as a wildcard and then as a regex
+    #[default]
+    #[serde(skip_deserializing)]
+    Legacy,
+    #[serde(rename = "glob")]
+    Glob,
+    #[serde(rename = "re")]
+    Regex,
+    #[serde(rename = "exact")]
+    Exact,
+}
+
+/// A match pattern written as `[glob|re|exact][/i]:pattern`, or as an object in the config
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+pub struct MatchPattern {
+    pub kind: PatternKind,
+    pub pattern: String,
+    #[serde(default)]
+    pub case_insensitive: bool,
+}
+
+/// A likely mistake in a rule
+#[derive(Clone, Debug, Display)]
+#[display("Rule {} ({alias}): {kind}", rule_index + 1)]
+pub struct Lint {
+    pub rule_index: usize,
+    pub alias:      String,
+    pub kind:       LintKind,
+}
+
+#[derive(Clone, Debug, Display)]
+pub enum LintKind {
+    #[display("Has no match fields, it never matches")]
+    NoConditions,
+    /// Devices fall through to later rules, so only when the winning rules set all of its devices
+    #[display("Never applies, rule {} ({alias}) above it matches every window", rule_index + 1)]
+    Unreachable {
+        rule_index: usize,
+        alias:      String,
+    },
+    #[display(
+        "Never applies, rule {} ({alias}) has the same conditions and takes precedence",
+        rule_index + 1
+    )]
+    Duplicate {
+        rule_index: usize,
+        alias:      String,
+    },
+    /// The pattern never matches
+    #[display("Invalid {field} pattern {pattern}: {error}")]
+    InvalidPattern {
+        field:   &'static str,
+        pattern: String,
+        error:   regex::Error,
+    },
+    /// The pattern still matches as a wildcard, a `glob:` prefix makes that explicit
+    #[display("The {field} pattern {pattern} is only matched as a wildcard, as a regex: {error}")]
+    InvalidLegacyRegex {
+        field:   &'static str,
+        pattern: String,
+        error:   regex::Error,
+    },
+    #[display("Unknown device {device_serial}")]
+    UnknownDevice { device_serial: DeviceSerial },
+    #[display(
+        "Profile index {profile_index} is out of range, {device_serial} has {profile_count} \
+         profile(s)"
+    )]
+    ProfileOutOfRange {
+        device_serial: DeviceSerial,
+        profile_index: ProfileIndex,
+        profile_count: usize,
+    },
+}
+
+/// The rules with their patterns compiled once, rebuilt when the config changes
+#[derive(Debug, Default)]
+pub struct RuleSet {
+    rules:    Vec<(Rule, CompiledConditions)>,
+    strategy: MatchStrategy,
+    errors:   Vec<Lint>,
+    /// Rank and index of the rules matching the last resolved window, reused between windows
+    matching: Vec<(Rank, usize)>,
+}
+
+/// The rules matching a window, borrowed from the rule set until the next resolve
+#[derive(Clone, Copy, Debug)]
+pub struct Resolution<'a> {
+    rules:    &'a [(Rule, CompiledConditions)],
+    matching: &'a [(Rank, usize)],
+}
+
+/// A switch held back until the window it was resolved for kept focus for the switch delay
+#[derive(Debug)]
+pub struct PendingSwitch<T> {
+    queued: Option<(Instant, T)>,
+}
+
+/// Specificity and priority of a matching rule, best first
+type Rank = Reverse<((usize, usize), i32)>;
+
+#[derive(Debug)]
+struct CompiledConditions {
+    fields:     [Option<(CompiledPattern, Specificity)>; 4],
+    match_mode: MatchMode,
+    groups:     Vec<Self>,
+    negated:    bool,
+}
+
+#[derive(Debug)]
+enum CompiledPattern {
+    Legacy {
+        glob:  Regex,
+        regex: Result<Regex, regex::Error>,
+    },
+    Regex(Regex),
+    Exact(String),
+    Invalid,
+}
+
+/// How much a matched field narrows down the window, in increasing order
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+enum Specificity {
+    Wildcard,
+    Pattern,
+    Exact,
+}
+
+/* Traits */
+
+/// The match fields and nested groups shared by rules and match groups
+pub trait Conditions {
+    /// The app name, bin name, bin path and win name patterns
+    fn match_fields(&self) -> [Option<&str>; 4];
+    fn match_mode(&self) -> MatchMode;
+    fn match_groups(&self) -> &[MatchGroup];
+
+    fn negated(&self) -> bool {
+        false
+    }
+
+    /// Whether the conditions are sure to match every window
+    fn matches_everything(&self) -> bool {
+        let mut results = self
+            .match_fields()
+            .into_iter()
+            .flatten()
+            .map(|pattern| MatchPattern::from(pattern).matches_everything())
+            .chain(
+                self.match_groups()
+                    .iter()
+                    .map(Conditions::matches_everything),
+            )
+            .peekable();
+
+        !self.negated()
+            && results.peek().is_some()
+            && match self.match_mode() {
+                MatchMode::Any => results.any(|matched| matched),
+                MatchMode::All => results.all(|matched| matched),
+            }
+    }
+
+    fn has_conditions(&self) -> bool {
+        self.match_fields().iter().any(Option::is_some) || !self.match_groups().is_empty()
+    }
+
+    fn same_conditions(&self, other: &impl Conditions) -> bool {
+        self.match_fields() == other.match_fields()
+            && self.match_mode() == other.match_mode()
+            && self.match_groups() == other.match_groups()
+            && self.negated() == other.negated()
+    }
+}
+
+/* Implementations */
+
+impl Conditions for Rule {
+    fn match_fields(&self) -> [Option<&str>; 4] {
+        [
+            self.match_app_name.as_deref(),
+            self.match_bin_name.as_deref(),
+            self.match_bin_path.as_deref(),
+            self.match_win_name.as_deref(),
+        ]
+    }
+
+    fn match_mode(&self) -> MatchMode {
+        self.match_mode
+    }
+
+    fn match_groups(&self) -> &[MatchGroup] {
+        &self.match_groups
+    }
+}
+
+impl Conditions for MatchGroup {
+    fn match_fields(&self) -> [Option<&str>; 4] {
+        [
+            self.match_app_name.as_deref(),
+            self.match_bin_name.as_deref(),
+            self.match_bin_path.as_deref(),
+            self.match_win_name.as_deref(),
+        ]
+    }
+
+    fn match_mode(&self) -> MatchMode {
+        self.match_mode
+    }
+
+    fn match_groups(&self) -> &[MatchGroup] {
+        &self.match_groups
+    }
+
+    fn negated(&self) -> bool {
+        self.not
+    }
+}
+
+impl PatternKind {
+    const fn prefix(self) -> &'static str {
+        match self {
+            Self::Legacy => "",
+            Self::Glob => "glob",
+            Self::Regex => "re",
+            Self::Exact => "exact",
+        }
+    }
+
+    pub const fn label(self) -> &'static str {
+        match self {
+            Self::Legacy => "Wildcard or Regex",
+            Self::Glob => "Wildcard",
+            Self::Regex => "Regex",
+            Self::Exact => "Exact",
+        }
+    }
+}
+
+impl From<&str> for MatchPattern {
+    // Anything without a known prefix is a legacy pattern
+    fn from(value: &str) -> Self {
+        let Some((prefix, pattern)) = value.split_once(':') else {
+            return Self::legacy(value);
+        };
+
+        let (prefix, case_insensitive) = prefix
+            .strip_suffix("/i")
+            .map_or((prefix, false), |prefix| (prefix, true));
+
+        let kind = match prefix {
+            "glob" => PatternKind::Glob,
+            "re" => PatternKind::Regex,
+            "exact" => PatternKind::Exact,
+            _ => return Self::legacy(value),
+        };
+
+        Self {
+            kind,
+            pattern: pattern.to_owned(),
+            case_insensitive,
+        }
+    }
+}
+
+impl fmt::Display for MatchPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.kind != PatternKind::Legacy {
+            let flag = if self.case_insensitive { "/i" } else { "" };
+            write!(f, "{}{flag}:", self.kind.prefix())?;
+        }
+
+        write!(f, "{}", self.pattern)
+    }
+}
+
+impl MatchPattern {
+    /// A pattern matching exactly the value
+    pub fn exact(pattern: &str) -> Self {
+        Self {
+            kind: PatternKind::Exact,
+            pattern: pattern.to_owned(),
+            case_insensitive: false,
+        }
+    }
+
+    fn legacy(pattern: &str) -> Self {
+        Self {
+            pattern: pattern.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    fn matches_everything(&self) -> bool {
+        let glob = !self.pattern.is_empty() && self.pattern.chars().all(|char| char == '*');
+        let regex = MATCH_ANYTHING.contains(&self.pattern.as_str());
+        match self.kind {
+            PatternKind::Legacy => glob || regex,
+            PatternKind::Glob => glob,
+            PatternKind::Regex => regex,
+            PatternKind::Exact => false,
+        }
+    }
+
+    fn specificity(&self) -> Specificity {
+        match self.kind {
+            _ if self.matches_everything() => Specificity::Wildcard,
+            PatternKind::Exact => Specificity::Exact,
+            _ => Specificity::Pattern,
+        }
+    }
+
+    /// Compile the pattern, legacy patterns that aren't a valid regex are only a wildcard
+    pub fn compile(&self) -> Result<Regex, regex::Error> {
+        let case_insensitive = self.case_insensitive;
+        match self.kind {
+            PatternKind::Legacy | PatternKind::Glob => glob_regex(&self.pattern, case_insensitive),
+            PatternKind::Regex => RegexBuilder::new(&self.pattern)
+                .case_insensitive(case_insensitive)
+                .build(),
+            PatternKind::Exact => RegexBuilder::new(&format!("^{}$", regex::escape(&self.pattern)))
+                .case_insensitive(case_insensitive)
+                .build(),
+        }
+    }
+}
+
+impl CompiledPattern {
+    fn new(pattern: &MatchPattern) -> Result<Self, regex::Error> {
+        Ok(match pattern.kind {
+            PatternKind::Legacy => Self::Legacy {
+                glob:  pattern.compile()?,
+                regex: Regex::new(&pattern.pattern),
+            },
+            PatternKind::Exact if !pattern.case_insensitive => Self::Exact(pattern.pattern.clone()),
+            _ => Self::Regex(pattern.compile()?),
+        })
+    }
+
+    fn is_match(&self, value: &str) -> bool {
+        match self {
+            Self::Legacy { glob, regex } => {
+                glob.is_match(value) || regex.as_ref().is_ok_and(|re| re.is_match(value))
+            }
+            Self::Regex(re) => re.is_match(value),
+            Self::Exact(pattern) => pattern == value,
+            Self::Invalid => false,
+        }
+    }
+}
+
+impl CompiledConditions {
+    fn new<C: Conditions>(
+        conditions: &C,
+        rule_index: usize,
+        alias: &str,
+        errors: &mut Vec<Lint>,
+    ) -> Self {
+        let patterns = conditions.match_fields();
+        let fields = std::array::from_fn(|i| {
+            let pattern = MatchPattern::from(patterns[i]?);
+            let compiled = CompiledPattern::new(&pattern).unwrap_or_else(|error| {
+                errors.push(Lint {
+                    rule_index,
+                    alias: alias.to_owned(),
+                    kind: LintKind::InvalidPattern {
+                        field: MATCH_FIELDS[i],
+                        pattern: pattern.to_string(),
+                        error,
+                    },
+                });
+                CompiledPattern::Invalid
+            });
+
+            // A wildcard matching everything is never meant as a regex
+            if let CompiledPattern::Legacy {
+                regex: Err(error), ..
+            } = &compiled
+            {
+                if !pattern.matches_everything() {
+                    errors.push(Lint {
+                        rule_index,
+                        alias: alias.to_owned(),
+                        kind: LintKind::InvalidLegacyRegex {
+                            field:   MATCH_FIELDS[i],
+                            pattern: pattern.to_string(),
+                            error:   error.clone(),
+                        },
+                    });
+                }
+            }
+
+            Some((compiled, pattern.specificity()))
+        });
+
+        let groups = conditions
+            .match_groups()
+            .iter()
+            .map(|group| Self::new(group, rule_index, alias, errors))
+            .collect();
+
+        Self {
+            fields,
+            match_mode: conditions.match_mode(),
+            groups,
+            negated: conditions.negated(),
+        }
+    }
+
+    /// Whether the window satisfies the conditions, no conditions never match
+    fn matches(&self, window: &ActiveMatchInfo) -> bool {
+        let values = [
+            &window.app_name,
+            &window.bin_name,
+            &window.bin_path,
+            &window.win_name,
+        ];
+
+        let mut results = self
+            .fields
+            .iter()
+            .zip(values)
+            .filter_map(|(field, value)| Some(field.as_ref()?.0.is_match(value)))
+            .chain(self.groups.iter().map(|group| group.matches(window)))
+            .peekable();
+
+        let matched = results.peek().is_some()
+            && match self.match_mode {
+                MatchMode::Any => results.any(|matched| matched),
+                MatchMode::All => results.all(|matched| matched),
+            };
+
+        matched != self.negated
+    }
+
+    /// The number of exact and of other non-wildcard fields matching the window, including
+    /// those of matching groups
+    fn specificity(&self, window: &ActiveMatchInfo) -> (usize, usize) {
+        let values = [
+            &window.app_name,
+            &window.bin_name,
+            &window.bin_path,
+            &window.win_name,
+        ];
+
+        let fields = self
+            .fields
+            .iter()
+            .zip(values)
+            .filter_map(|(field, value)| {
+                field
+                    .as_ref()
+                    .filter(|(pattern, _)| pattern.is_match(value))
+            })
+            .fold(
+                (0, 0),
+                |(exact, patterns), (_, specificity)| match specificity {
+                    Specificity::Exact => (exact + 1, patterns),
+                    Specificity::Pattern => (exact, patterns + 1),
+                    Specificity::Wildcard => (exact, patterns),
+                },
+            );
+
+        self.groups
+            .iter()
+            .filter(|group| !group.negated && group.matches(window))
+            .map(|group| group.specificity(window))
+            .fold(fields, |(exact, patterns), group| {
+                (exact + group.0, patterns + group.1)
+            })
+    }
+}
+
+impl RuleSet {
+    pub fn new(rules: &[Rule], strategy: MatchStrategy) -> Self {
+        let mut errors = Vec::new();
+        let rules = rules
+            .iter()
+            .enumerate()
+            .map(|(rule_index, rule)| {
+                let conditions =
+                    CompiledConditions::new(rule, rule_index, &rule.alias, &mut errors);
+                (rule.clone(), conditions)
+            })
+            .collect();
+
+        Self {
+            rules,
+            strategy,
+            errors,
+            matching: Vec::new(),
+        }
+    }
+
+    /// The patterns that failed to compile
+    pub fn errors(&self) -> &[Lint] {
+        &self.errors
+    }
+
+    /// Rank the rules matching the window, each device then falls through to the next best
+    /// matching rule when a rule skips it or doesn't list it
+    pub fn resolve(&mut self, window: &ActiveMatchInfo) -> Resolution<'_> {
+        self.matching.clear();
+        for (rule_index, (rule, conditions)) in self.rules.iter().enumerate() {
+            if !conditions.matches(window) {
+                continue;
+            }
+
+            let rank = match self.strategy {
+                MatchStrategy::MostSpecific => {
+                    Reverse((conditions.specificity(window), rule.priority))
+                }
+                MatchStrategy::FirstMatch => Reverse::default(),
+            };
+            self.matching.push((rank, rule_index));
+        }
+
+        // Ranks tie on the rule index, so the first in the list wins the remaining ties
+        self.matching.sort_unstable();
+
+        Resolution {
+            rules:    &self.rules,
+            matching: &self.matching,
+        }
+    }
+}
+
+impl<'a> Resolution<'a> {
+    /// The best matching rule according to the match strategy
+    pub fn rule(&self) -> Option<&'a Rule> {
+        let &(_, rule_index) = self.matching.first()?;
+        Some(&self.rules[rule_index].0)
+    }
+
+    /// How long the window must keep focus before the best matching rule is applied
+    pub fn switch_delay(&self, default_delay_ms: u64) -> Duration {
+        let switch_delay_ms = self
+            .rule()
+            .and_then(|rule| rule.switch_delay_ms)
+            .unwrap_or(default_delay_ms);
+
+        Duration::from_millis(switch_delay_ms)
+    }
+
+    /// The profile index and lighting mode of each device, taken from the best matching rule
+    /// that doesn't skip it
+    pub fn profiles(
+        &self,
+    ) -> impl Iterator<Item = (&'a DeviceSerial, ProfileIndex, Option<LightingMode>)> {
+        let Self { rules, matching } = *self;
+        let rule = move |&(_, rule_index): &(Rank, usize)| &rules[rule_index].0;
+
+        matching
+            .iter()
+            .enumerate()
+            .flat_map(move |(position, ranked)| {
+                let best = rule(ranked);
+                best.device_indices
+                    .iter()
+                    .filter(move |&(device_serial, &profile_index)| {
+                        profile_index >= 0
+                            && !matching[..position].iter().map(rule).any(|better| {
+                                better
+                                    .device_indices
+                                    .get(device_serial)
+                                    .is_some_and(|&profile_index| profile_index >= 0)
+                            })
+                    })
+                    .map(move |(device_serial, &profile_index)| {
+                        (
+                            device_serial,
+                            profile_index,
+                            best.lighting_mode(device_serial),
+                        )
+                    })
+            })
+    }
+}
+
+impl<T> Default for PendingSwitch<T> {
+    fn default() -> Self {
+        Self { queued: None }
+    }
+}
+
+impl<T> PendingSwitch<T> {
+    /// Queue the switch for a newly focused window, cancelling the switch for the window
+    /// that lost focus
+    pub fn queue(&mut self, switch: T, delay: Duration, now: Instant) {
+        self.queued = Some((now + delay, switch));
+    }
+
+    /// Take the switch once its window kept focus for the delay
+    ///
+    /// While paused the switch stays queued, so it's applied once switching is resumed.
+    pub fn take_due(&mut self, now: Instant, paused: bool) -> Option<T> {
+        if paused {
+            return None;
+        }
+
+        self.queued
+            .take_if(|(due, _)| now >= *due)
+            .map(|(_, switch)| switch)
+    }
+}
+
+/* Functions */
+
+// Finds rules that can never match and targets that don't exist, in rule order
+pub fn lint(config: &Config) -> Vec<Lint> {
+    let mut lints = RuleSet::new(&config.rules, config.match_strategy).errors;
+    for (rule_index, rule) in config.rules.iter().enumerate() {
+        let mut push = |kind| {
+            lints.push(Lint {
+                rule_index,
+                alias: rule.alias.clone(),
+                kind,
+            });
+        };
+
+        // Rules that win whenever this one matches, it only applies to the devices they skip
+        let most_specific = config.match_strategy == MatchStrategy::MostSpecific;
+        let shadowing = config
+            .rules
+            .iter()
+            .enumerate()
+            .filter(|&(other_index, other)| {
+                if most_specific {
+                    let rank = |priority, index| (priority, Reverse(index));
+                    rule.same_conditions(other)
+                        && rank(other.priority, other_index) > rank(rule.priority, rule_index)
+                } else {
+                    other_index < rule_index
+                        && (other.matches_everything() || rule.same_conditions(other))
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let duplicate = shadowing
+            .iter()
+            .find(|(_, other)| rule.same_conditions(*other));
+        let catch_all = shadowing
+            .iter()
+            .find(|(_, other)| other.matches_everything());
+        let shadowed = rule
+            .device_indices
+            .iter()
+            .filter(|(_, &profile_index)| profile_index >= 0)
+            .all(|(device_serial, _)| {
+                shadowing.iter().any(|(_, other)| {
+                    other
+                        .device_indices
+                        .get(device_serial)
+                        .is_some_and(|&profile_index| profile_index >= 0)
+                })
+            });
+
+        if !rule.has_conditions() {
+            push(LintKind::NoConditions);
+        } else if let Some((index, other)) = duplicate.filter(|_| shadowed) {
+            push(LintKind::Duplicate {
+                rule_index: *index,
+                alias:      other.alias.clone(),
+            });
+        } else if let Some((index, other)) = catch_all.filter(|_| shadowed) {
+            push(LintKind::Unreachable {
+                rule_index: *index,
+                alias:      other.alias.clone(),
+            });
+        }
+
+        // Sorted so the output is stable
+        let mut device_serials = rule
+            .device_indices
+            .keys()
+            .chain(rule.lighting_modes.keys())
+            .collect::<Vec<_>>();
+        device_serials.sort_by_cached_key(ToString::to_string);
+        device_serials.dedup();
+
+        for device_serial in device_serials {
+            let Some(device) = config.devices.get(device_serial) else {
+                push(LintKind::UnknownDevice {
+                    device_serial: device_serial.clone(),
+                });
+                continue;
+            };
+
+            // Negative indices skip the device
+            let Some(&profile_index) = rule.device_indices.get(device_serial) else {
+                continue;
+            };
+            let Ok(index) = usize::try_from(profile_index) else {
+                continue;
+            };
+
+            // The saved profile names if there are any, otherwise the reported slots
+            let profile_count = if device.profiles.is_empty() {
+                device.profile_slots().map(usize::from)
+            } else {
+                Some(device.available_profiles().len())
+            };
+
+            if let Some(profile_count) = profile_count.filter(|&count| index >= count) {
+                push(LintKind::ProfileOutOfRange {
+                    device_serial: device_serial.clone(),
+                    profile_index,
+                    profile_count,
+                });
+            }
+        }
+    }
+
+    lints.sort_by_key(|lint| lint.rule_index);
+
+    lints
+}
+
+/* Helpers */
+
+// Wildcards become an anchored regex, backslashes are literal so Windows paths work
+fn glob_regex(pattern: &str, case_insensitive: bool) -> Result<Regex, regex::Error> {
+    let mut re = String::from("^");
+    for char in pattern.chars() {
+        match char {
+            '*' => re.push_str(".*"),
+            '?' => re.push('.'),
+            char => re.push_str(&regex::escape(char.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    re.push('$');
+
+    RegexBuilder::new(&re)
+        .case_insensitive(case_insensitive)
+        .dot_matches_new_line(true)
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{iter, slice};
+
+    use super::*;
+
+    const KEYBOARD: &str = "A02B2106W031H00418";
+    const MACRO_PAD: &str = "A02B2106W031H00419";
+
+    fn serial(serial: &str) -> DeviceSerial {
+        serial.parse().unwrap()
+    }
+
+    fn rule(alias: &str, match_bin_name: &str) -> Rule {
+        Rule {
+            alias: alias.to_owned(),
+            match_bin_name: Some(match_bin_name.to_owned()),
+            ..Rule::default()
+        }
+    }
+
+    fn with_devices(rule: Rule, device_indices: &[(&str, ProfileIndex)]) -> Rule {
+        Rule {
+            device_indices: device_indices
+                .iter()
+                .map(|&(device_serial, profile_index)| (serial(device_serial), profile_index))
+                .collect(),
+            ..rule
+        }
+    }
+
+    fn window(bin_name: &str, win_name: &str) -> ActiveMatchInfo {
+        ActiveMatchInfo {
+            app_name: bin_name.trim_end_matches(".exe").to_owned(),
+            bin_name: bin_name.to_owned(),
+            bin_path: format!("C:\\Games\\{bin_name}"),
+            win_name: win_name.to_owned(),
+        }
+    }
+
+    // Whether the rule matches the window once compiled
+    fn matches(rule: &Rule, window: &ActiveMatchInfo) -> bool {
+        RuleSet::new(slice::from_ref(rule), MatchStrategy::FirstMatch)
+            .resolve(window)
+            .rule()
+            .is_some()
+    }
+
+    // The alias of the best matching rule and the profile index of each device, by serial
+    fn resolve(
+        rule_set: &mut RuleSet,
+        window: &ActiveMatchInfo,
+    ) -> (String, Vec<(String, ProfileIndex)>) {
+        let resolution = rule_set.resolve(window);
+        let alias = resolution
+            .rule()
+            .map(|rule| rule.alias.clone())
+            .unwrap_or_default();
+        let mut profiles = resolution
+            .profiles()
+            .map(|(device_serial, profile_index, _)| (device_serial.to_string(), profile_index))
+            .collect::<Vec<_>>();
+        profiles.sort();
+
+        (alias, profiles)
+    }
+
+    // Whether a rule with only the window name pattern matches the window name
+    fn matches_title(pattern: &str, win_name: &str) -> bool {
+        let rule = Rule {
+            match_win_name: Some(pattern.to_owned()),
+            ..Rule::default()
+        };
+
+        matches(&rule, &window("game.exe", win_name))
+    }
+
+    // The rules linted as duplicate or unreachable, with the rule taking precedence over them
+    fn shadowed(
+        rules: Vec<Rule>,
+        match_strategy: MatchStrategy,
+    ) -> Vec<(usize, &'static str, usize)> {
+        let config = Config {
+            match_strategy,
+            rules,
+            ..Config::default()
+        };
+
+        lint(&config)
+            .into_iter()
+            .filter_map(|lint| match lint.kind {
+                LintKind::Duplicate { rule_index, .. } => {
+                    Some((lint.rule_index, "duplicate", rule_index))
+                }
+                LintKind::Unreachable { rule_index, .. } => {
+                    Some((lint.rule_index, "unreachable", rule_index))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn never_matches_without_conditions() {
+        assert!(!matches(&Rule::default(), &window("game.exe", "Game")));
+    }
+
+    #[test]
+    fn matches_any_or_all_fields() {
+        let any = Rule {
+            match_win_name: Some(String::from("*Ranked*")),
+            ..rule("Any", "game.exe")
+        };
+        let all = Rule {
+            match_mode: MatchMode::All,
+            ..any.clone()
+        };
+
+        assert!(matches(&any, &window("game.exe", "Lobby")));
+        assert!(matches(&any, &window("other.exe", "Ranked")));
+        assert!(!matches(&any, &window("other.exe", "Lobby")));
+
+        assert!(matches(&all, &window("game.exe", "Ranked")));
+        assert!(!matches(&all, &window("game.exe", "Lobby")));
+        assert!(!matches(&all, &window("other.exe", "Ranked")));
+    }
+
+    #[test]
+    fn matches_nested_groups_as_one_field() {
+        let rule = Rule {
+            match_groups: vec![MatchGroup {
+                match_bin_path: Some(String::from("C:\\Games\\*")),
+                match_win_name: Some(String::from("*Ranked*")),
+                match_mode: MatchMode::All,
+                ..MatchGroup::default()
+            }],
+            ..rule("Game", "game.exe")
+        };
+
+        assert!(matches(&rule, &window("game.exe", "Lobby")));
+        assert!(matches(&rule, &window("other.exe", "Ranked")));
+        assert!(!matches(&rule, &window("other.exe", "Lobby")));
+    }
+
+    #[test]
+    fn matches_negated_groups() {
+        let rule = Rule {
+            match_mode: MatchMode::All,
+            match_groups: vec![MatchGroup {
+                match_app_name: Some(String::from("*launcher")),
+                match_win_name: Some(String::from("*Launcher*")),
+                not: true,
+                ..MatchGroup::default()
+            }],
+            ..rule("Game", "game*.exe")
+        };
+
+        assert!(matches(&rule, &window("game.exe", "Ranked")));
+        assert!(!matches(&rule, &window("game.exe", "Launcher")));
+        assert!(!matches(&rule, &window("game_launcher.exe", "Game")));
+        assert!(!matches(&rule, &window("other.exe", "Ranked")));
+    }
+
+    #[test]
+    fn parses_and_displays_pattern_prefixes() {
+        for pattern in [
+            "glob:*Ranked*",
+            "glob/i:*ranked*",
+            "re:^Game \\d+$",
+            "exact/i:game",
+        ] {
+            assert_eq!(MatchPattern::from(pattern).to_string(), pattern);
+        }
+
+        let pattern = MatchPattern::from("re/i:^game");
+        assert_eq!(pattern.kind, PatternKind::Regex);
+        assert!(pattern.case_insensitive);
+
+        // Unknown prefixes are part of a legacy pattern
+        let pattern = MatchPattern::from("Steam: Library");
+        assert_eq!(pattern.kind, PatternKind::Legacy);
+        assert_eq!(pattern.pattern, "Steam: Library");
+    }
+
+    #[test]
+    fn matches_legacy_patterns_as_wildcards_or_regexes() {
+        assert!(matches_title("Game*", "Game 42"));
+        assert!(!matches_title("Game*", "game 42"));
+        assert!(matches_title("C:\\Games\\*", "C:\\Games\\game.exe"));
+        assert!(matches_title("^Game \\d+$", "Game 42"));
+    }
+
+    #[test]
+    fn matches_glob_patterns() {
+        assert!(matches_title("glob:*Ranked*", "Game - Ranked Match"));
+        assert!(!matches_title("glob:*Ranked*", "Game - ranked match"));
+        assert!(matches_title("glob/i:*ranked*", "Game - Ranked Match"));
+        assert!(!matches_title("glob:Game", "Game - Ranked Match"));
+    }
+
+    #[test]
+    fn matches_regex_patterns() {
+        assert!(matches_title("re:^Game \\d+$", "Game 42"));
+        assert!(!matches_title("re:^Game \\d+$", "game 42"));
+        assert!(matches_title("re/i:^game \\d+$", "GAME 42"));
+        assert!(!matches_title("re:^Game \\d+$", "Game 42 - Lobby"));
+    }
+
+    #[test]
+    fn matches_exact_patterns() {
+        assert!(matches_title("exact:Game*", "Game*"));
+        assert!(!matches_title("exact:Game*", "Game 42"));
+        assert!(!matches_title("exact:Game", "game"));
+        assert!(matches_title("exact/i:Game", "GAME"));
+        assert!(!matches_title("exact/i:Game", "GAME 42"));
+    }
+
+    #[test]
+    fn reports_invalid_patterns_and_never_matches_them() {
+        let rule = Rule {
+            match_win_name: Some(String::from("re:(")),
+            ..rule("Broken", "game.exe")
+        };
+        let rule_set = RuleSet::new(slice::from_ref(&rule), MatchStrategy::FirstMatch);
+        assert!(matches!(
+            rule_set.errors(),
+            [Lint {
+                kind: LintKind::InvalidPattern {
+                    field: "match_win_name",
+                    ..
+                },
+                ..
+            }]
+        ));
+
+        // The valid bin name pattern still matches on its own
+        assert!(matches(&rule, &window("game.exe", "Game")));
+        assert!(!matches_title("re:(", "("));
+    }
+
+    #[test]
+    fn reports_legacy_patterns_that_are_only_a_wildcard() {
+        let rule = Rule {
+            match_win_name: Some(String::from("Game (Ranked")),
+            ..rule("Legacy", "*")
+        };
+        let rule_set = RuleSet::new(slice::from_ref(&rule), MatchStrategy::FirstMatch);
+        assert!(matches!(
+            rule_set.errors(),
+            [Lint {
+                kind: LintKind::InvalidLegacyRegex {
+                    field: "match_win_name",
+                    ..
+                },
+                ..
+            }]
+        ));
+
+        // The wildcard still matches
+        assert!(matches_title("Game (Ranked", "Game (Ranked"));
+        assert!(!matches_title("Game (Ranked", "Game"));
+    }
+
+    #[test]
+    fn lints_rules_without_conditions() {
+        let config = Config {
+            rules: vec![Rule::default()],
+            ..Config::default()
+        };
+
+        assert!(matches!(
+            lint(&config)[..],
+            [Lint {
+                rule_index: 0,
+                kind: LintKind::NoConditions,
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn lints_unreachable_rules() {
+        let rules = vec![rule("Fallback", "*"), rule("Game", "game.exe")];
+        assert_eq!(
+            shadowed(rules, MatchStrategy::FirstMatch),
+            [(1, "unreachable", 0)]
+        );
+
+        // Rules above a catch-all rule still match first
+        let rules = vec![rule("Game", "game.exe"), rule("Fallback", "*")];
+        assert!(shadowed(rules, MatchStrategy::FirstMatch).is_empty());
+    }
+
+    #[test]
+    fn lints_duplicate_rules() {
+        let rules = vec![
+            rule("First", "exact:game.exe"),
+            rule("Second", "exact:game.exe"),
+            rule("Third", "exact:other.exe"),
+        ];
+        assert_eq!(
+            shadowed(rules, MatchStrategy::FirstMatch),
+            [(1, "duplicate", 0)]
+        );
+
+        // The same patterns combined differently aren't duplicates
+        let rules = vec![
+            Rule {
+                match_win_name: Some(String::from("Game")),
+                ..rule("Any", "game.exe")
+            },
+            Rule {
+                match_win_name: Some(String::from("Game")),
+                match_mode: MatchMode::All,
+                ..rule("All", "game.exe")
+            },
+        ];
+        assert!(shadowed(rules, MatchStrategy::FirstMatch).is_empty());
+    }
+
+    #[test]
+    fn lints_unknown_devices() {
+        let config = Config {
+            rules: vec![Rule {
+                device_indices: iter::once((serial(KEYBOARD), 0)).collect(),
+                ..rule("Game", "game.exe")
+            }],
+            ..Config::default()
+        };
+
+        assert!(matches!(
+            &lint(&config)[..],
+            [Lint {
+                kind: LintKind::UnknownDevice { device_serial },
+                ..
+            }] if device_serial.to_string() == KEYBOARD
+        ));
+    }
+
+    #[test]
+    fn falls_through_to_the_next_rule_for_skipped_devices() {
+        let rules = [
+            with_devices(rule("Game", "game.exe"), &[(KEYBOARD, 2), (MACRO_PAD, -1)]),
+            with_devices(rule("Fallback", "*"), &[(KEYBOARD, 0), (MACRO_PAD, 1)]),
+        ];
+        let mut rule_set = RuleSet::new(&rules, MatchStrategy::FirstMatch);
+
+        assert_eq!(
+            resolve(&mut rule_set, &window("game.exe", "Game")),
+            (
+                String::from("Game"),
+                vec![(KEYBOARD.to_owned(), 2), (MACRO_PAD.to_owned(), 1)]
+            )
+        );
+    }
+
+    #[test]
+    fn falls_through_to_the_next_rule_for_unlisted_devices() {
+        let rules = [
+            with_devices(rule("Game", "game.exe"), &[(KEYBOARD, 2)]),
+            with_devices(rule("Editor", "editor.exe"), &[(MACRO_PAD, 3)]),
+            with_devices(rule("Fallback", "*"), &[(KEYBOARD, 0), (MACRO_PAD, 1)]),
+        ];
+        let mut rule_set = RuleSet::new(&rules, MatchStrategy::FirstMatch);
+
+        assert_eq!(
+            resolve(&mut rule_set, &window("game.exe", "Game")),
+            (
+                String::from("Game"),
+                vec![(KEYBOARD.to_owned(), 2), (MACRO_PAD.to_owned(), 1)]
+            )
+        );
+        assert_eq!(
+            resolve(&mut rule_set, &window("editor.exe", "Editor")),
+            (
+                String::from("Editor"),
+                vec![(KEYBOARD.to_owned(), 0), (MACRO_PAD.to_owned(), 3)]
+            )
+        );
+    }
+
+    #[test]
+    fn leaves_devices_no_matching_rule_sets() {
+        let rules = [
+            with_devices(rule("Game", "game.exe"), &[(KEYBOARD, 2), (MACRO_PAD, -1)]),
+            with_devices(rule("Launcher", "launcher.exe"), &[(MACRO_PAD, 1)]),
+        ];
+        let mut rule_set = RuleSet::new(&rules, MatchStrategy::FirstMatch);
+
+        assert_eq!(
+            resolve(&mut rule_set, &window("game.exe", "Game")),
+            (String::from("Game"), vec![(KEYBOARD.to_owned(), 2)])
+        );
+        assert_eq!(
+            resolve(&mut rule_set, &window("other.exe", "Other")),
+            (String::new(), Vec::new())
+        );
+    }
+
+    #[test]
+    fn takes_lighting_modes_from_the_rule_setting_the_device() {
+        let game = Rule {
+            lighting_mode: Some(LightingMode::ProfileOnly),
+            ..with_devices(rule("Game", "game.exe"), &[(KEYBOARD, 2), (MACRO_PAD, -1)])
+        };
+        let fallback = Rule {
+            lighting_modes: iter::once((serial(MACRO_PAD), LightingMode::LightingOnly)).collect(),
+            ..with_devices(rule("Fallback", "*"), &[(KEYBOARD, 0), (MACRO_PAD, 1)])
+        };
+        let mut rule_set = RuleSet::new(&[game, fallback], MatchStrategy::FirstMatch);
+
+        let mut lighting_modes = rule_set
+            .resolve(&window("game.exe", "Game"))
+            .profiles()
+            .filter_map(|(device_serial, _, lighting_mode)| {
+                Some((device_serial.to_string(), lighting_mode?))
+            })
+            .collect::<Vec<_>>();
+        lighting_modes.sort_by(|(a, _), (b, _)| a.cmp(b));
+        assert_eq!(
+            lighting_modes,
+            [
+                (KEYBOARD.to_owned(), LightingMode::ProfileOnly),
+                (MACRO_PAD.to_owned(), LightingMode::LightingOnly),
+            ]
+        );
+    }
+
+    #[test]
+    fn lints_only_rules_whose_devices_are_all_set_above() {
+        // A catch-all rule skipping a device lets later rules set it
+        let rules = vec![
+            with_devices(rule("Fallback", "*"), &[(KEYBOARD, 0), (MACRO_PAD, -1)]),
+            with_devices(rule("Game", "game.exe"), &[(KEYBOARD, 2), (MACRO_PAD, 1)]),
+            with_devices(rule("Editor", "editor.exe"), &[(KEYBOARD, 3)]),
+        ];
+        assert_eq!(
+            shadowed(rules, MatchStrategy::FirstMatch),
+            [(2, "unreachable", 0)]
+        );
+
+        // So does a duplicate that doesn't list a device
+        let rules = vec![
+            with_devices(rule("First", "exact:game.exe"), &[(KEYBOARD, 1)]),
+            with_devices(
+                rule("Second", "exact:game.exe"),
+                &[(KEYBOARD, 2), (MACRO_PAD, 1)],
+            ),
+        ];
+        assert!(shadowed(rules, MatchStrategy::FirstMatch).is_empty());
+    }
+
+    #[test]
+    fn orders_rules_by_strategy() {
+        let rules = [
+            rule("Fallback", "*"),
+            Rule {
+                match_bin_path: Some(String::from("C:\\Games\\*")),
+                ..rule("Games", "*.exe")
+            },
+            rule("Game", "exact:game.exe"),
+        ];
+        let game = window("game.exe", "Game");
+        let other = window("other.exe", "Other");
+        let tool = ActiveMatchInfo {
+            bin_path: String::from("C:\\Tools\\tool"),
+            ..window("tool", "Tool")
+        };
+
+        let mut rule_set = RuleSet::new(&rules, MatchStrategy::FirstMatch);
+        assert_eq!(resolve(&mut rule_set, &game).0, "Fallback");
+        assert_eq!(resolve(&mut rule_set, &other).0, "Fallback");
+
+        // Exact fields first, then other patterns, a wildcard doesn't count
+        let mut rule_set = RuleSet::new(&rules, MatchStrategy::MostSpecific);
+        assert_eq!(resolve(&mut rule_set, &game).0, "Game");
+        assert_eq!(resolve(&mut rule_set, &other).0, "Games");
+        assert_eq!(resolve(&mut rule_set, &tool).0, "Fallback");
+    }
+
+    #[test]
+    fn counts_fields_of_matching_groups_as_specific() {
+        let rules = [
+            rule("Game", "game*.exe"),
+            Rule {
+                match_mode: MatchMode::All,
+                match_groups: vec![MatchGroup {
+                    match_win_name: Some(String::from("exact:Ranked")),
+                    ..MatchGroup::default()
+                }],
+                ..rule("Ranked", "game*.exe")
+            },
+        ];
+        let mut rule_set = RuleSet::new(&rules, MatchStrategy::MostSpecific);
+
+        assert_eq!(
+            resolve(&mut rule_set, &window("game.exe", "Ranked")).0,
+            "Ranked"
+        );
+        assert_eq!(
+            resolve(&mut rule_set, &window("game.exe", "Lobby")).0,
+            "Game"
+        );
+    }
+
+    #[test]
+    fn breaks_specificity_ties_by_priority_then_order() {
+        let first = with_devices(rule("First", "exact:game.exe"), &[(KEYBOARD, 1)]);
+        let second = with_devices(rule("Second", "exact:game.exe"), &[(KEYBOARD, 2)]);
+        let game = window("game.exe", "Game");
+
+        let mut rule_set = RuleSet::new(
+            &[first.clone(), second.clone()],
+            MatchStrategy::MostSpecific,
+        );
+        assert_eq!(
+            resolve(&mut rule_set, &game),
+            (String::from("First"), vec![(KEYBOARD.to_owned(), 1)])
+        );
+
+        let rules = [
+            first,
+            Rule {
+                priority: 5,
+                ..second
+            },
+        ];
+        let mut rule_set = RuleSet::new(&rules, MatchStrategy::MostSpecific);
+        assert_eq!(
+            resolve(&mut rule_set, &game),
+            (String::from("Second"), vec![(KEYBOARD.to_owned(), 2)])
+        );
+
+        // Priority only ranks rules of the most specific strategy
+        let mut rule_set = RuleSet::new(&rules, MatchStrategy::FirstMatch);
+        assert_eq!(resolve(&mut rule_set, &game).0, "First");
+    }
+
+    #[test]
+    fn lints_by_rank_with_the_most_specific_strategy() {
+        // The catch-all rule is ranked last
+        let rules = vec![rule("Fallback", "*"), rule("Game", "game.exe")];
+        assert!(shadowed(rules, MatchStrategy::MostSpecific).is_empty());
+
+        let rules = vec![
+            rule("First", "exact:game.exe"),
+            rule("Second", "exact:game.exe"),
+        ];
+        assert_eq!(
+            shadowed(rules.clone(), MatchStrategy::MostSpecific),
+            [(1, "duplicate", 0)]
+        );
+
+        // A higher priority turns the tie around
+        let rules = vec![
+            rules[0].clone(),
+            Rule {
+                priority: 5,
+                ..rules[1].clone()
+            },
+        ];
+        assert_eq!(
+            shadowed(rules, MatchStrategy::MostSpecific),
+            [(0, "duplicate", 1)]
+        );
+    }
+
+    #[test]
+    fn takes_the_switch_delay_of_the_matched_rule() {
+        let rules = [
+            Rule {
+                switch_delay_ms: Some(0),
+                ..rule("Game", "game.exe")
+            },
+            rule("Tool", "tool.exe"),
+        ];
+        let mut rule_set = RuleSet::new(&rules, MatchStrategy::FirstMatch);
+        let default_delay = Duration::from_millis(300);
+
+        let game = rule_set
+            .resolve(&window("game.exe", "Game"))
+            .switch_delay(300);
+        assert_eq!(game, Duration::ZERO);
+        let tool = rule_set
+            .resolve(&window("tool.exe", "Tool"))
+            .switch_delay(300);
+        assert_eq!(tool, default_delay);
+        let other = rule_set
+            .resolve(&window("other.exe", "Other"))
+            .switch_delay(300);
+        assert_eq!(other, default_delay);
+    }
+
+    #[test]
+    fn applies_a_switch_once_its_window_kept_focus() {
+        let start = Instant::now();
+        let delay = Duration::from_millis(500);
+        let mut pending_switch = PendingSwitch::default();
+        pending_switch.queue("game", delay, start);
+
+        assert_eq!(pending_switch.take_due(start + delay / 2, false), None);
+        assert_eq!(pending_switch.take_due(start + delay, false), Some("game"));
+        assert_eq!(pending_switch.take_due(start + delay * 2, false), None);
+    }
+
+    #[test]
+    fn cancels_a_switch_when_focus_moves() {
+        let start = Instant::now();
+        let delay = Duration::from_millis(500);
+        let mut pending_switch = PendingSwitch::default();
+        pending_switch.queue("game", delay, start);
+        pending_switch.queue("editor", delay, start + delay / 2);
+
+        assert_eq!(pending_switch.take_due(start + delay, false), None);
+        assert_eq!(
+            pending_switch.take_due(start + delay / 2 + delay, false),
+            Some("editor")
+        );
+    }
+
+    #[test]
+    fn applies_a_switch_without_delay_right_away() {
+        let start = Instant::now();
+        let mut pending_switch = PendingSwitch::default();
+        pending_switch.queue("game", Duration::ZERO, start);
+
+        assert_eq!(pending_switch.take_due(start, false), Some("game"));
+    }
+
+    #[test]
+    fn keeps_a_switch_while_paused() {
+        let start = Instant::now();
+        let delay = Duration::from_millis(500);
+        let mut pending_switch = PendingSwitch::default();
+        pending_switch.queue("game", delay, start);
+
+        assert_eq!(pending_switch.take_due(start + delay, true), None);
+        assert_eq!(pending_switch.take_due(start + delay * 2, true), None);
+        assert_eq!(
+            pending_switch.take_due(start + delay * 2, false),
+            Some("game")
+        );
+    }
+}
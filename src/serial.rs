@@ -0,0 +1,454 @@
+use std::{fmt, str::FromStr};
+
+use derive_more::{Display, Error};
+use serde::{Deserialize, Serialize};
+
+use crate::{Device, DeviceSerial, Stage};
+
+/* Structures */
+
+#[derive(Clone, Debug, Display, Eq, Error, PartialEq)]
+pub enum SerialError {
+    #[display("Expected '{expected}' at offset {offset}")]
+    Expected { expected: char, offset: usize },
+    #[display("Invalid {field} at offset {offset}")]
+    InvalidField { field: &'static str, offset: usize },
+    #[display("Invalid stage at offset {offset}")]
+    InvalidStage { offset: usize },
+    #[display("Unexpected character at offset {offset}")]
+    Unexpected { offset: usize },
+}
+
+/// The fields encoded in a device serial, e.g. `A02B2106W031H00418`
+///
+/// Serials are laid out as `A{supplier}B{year}{week}W{product}[T{pcb_design}]{revision}
+/// [{minor_rev}][S{variant}]{stage}{product_id}`. The supplier and product are single
+/// bytes written as two hex digits, the revision is assumed to be below 100 when followed
+/// by a minor revision.
+#[derive(Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct SerialFields {
+    pub supplier:   u8,
+    pub year:       u32,
+    pub week:       u32,
+    pub product:    u8,
+    pub revision:   u32,
+    pub product_id: u32,
+    pub stage:      Stage,
+    pub variant:    Option<u32>,
+    pub pcb_design: Option<u32>,
+    pub minor_rev:  Option<u32>,
+}
+
+/// Cursor over the ASCII bytes of a serial
+struct Parser<'a> {
+    serial: &'a [u8],
+    offset: usize,
+}
+
+/* Implementations */
+
+impl From<&Device> for SerialFields {
+    fn from(device: &Device) -> Self {
+        Self {
+            supplier:   device.supplier,
+            year:       device.year,
+            week:       device.week,
+            product:    device.product,
+            revision:   device.revision,
+            product_id: device.product_id,
+            stage:      device.stage.clone(),
+            variant:    device.variant,
+            pcb_design: device.pcb_design,
+            minor_rev:  device.minor_rev,
+        }
+    }
+}
+
+impl From<SerialFields> for Device {
+    fn from(fields: SerialFields) -> Self {
+        Self {
+            supplier: fields.supplier,
+            year: fields.year,
+            week: fields.week,
+            product: fields.product,
+            revision: fields.revision,
+            product_id: fields.product_id,
+            stage: fields.stage,
+            variant: fields.variant,
+            pcb_design: fields.pcb_design,
+            minor_rev: fields.minor_rev,
+            ..Default::default()
+        }
+    }
+}
+
+impl fmt::Display for SerialFields {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "A{:02X}B{:02}{:02}W{:02X}",
+            self.supplier, self.year, self.week, self.product
+        )?;
+        if let Some(pcb_design) = self.pcb_design {
+            write!(f, "T{pcb_design:02}")?;
+        }
+        write!(f, "{}", self.revision)?;
+        if let Some(minor_rev) = self.minor_rev {
+            write!(f, "{minor_rev:02}")?;
+        }
+        if let Some(variant) = self.variant {
+            write!(f, "S{variant:02}")?;
+        }
+
+        write!(f, "{}{:05}", self.stage, self.product_id)
+    }
+}
+
+impl FromStr for SerialFields {
+    type Err = SerialError;
+
+    fn from_str(serial: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            serial: serial.as_bytes(),
+            offset: 0,
+        };
+
+        parser.expect(b'A')?;
+        let supplier = parser.hex_byte("supplier")?;
+        parser.expect(b'B')?;
+        let year = parser.number(2, 10, "year")?;
+        let week = parser.number(2, 10, "week")?;
+        parser.expect(b'W')?;
+        let product = parser.hex_byte("product")?;
+
+        // The revision is required, so a T directly after the product is the PCB design
+        let pcb_design = if parser.eat(b'T') {
+            Some(parser.number(2, 10, "PCB design")?)
+        } else {
+            None
+        };
+
+        let (revision, minor_rev) = parser.revision()?;
+        let variant = if parser.eat(b'S') {
+            let offset = parser.offset;
+            let digits = parser.digits();
+            if digits.len() < 2 {
+                return Err(SerialError::InvalidField {
+                    field: "variant",
+                    offset,
+                });
+            }
+            Some(parse_number(digits, 10, "variant", offset)?)
+        } else {
+            None
+        };
+
+        let stage = parser.stage()?;
+
+        let offset = parser.offset;
+        let digits = parser.digits();
+        if digits.len() < 5 {
+            return Err(SerialError::InvalidField {
+                field: "product ID",
+                offset,
+            });
+        }
+        let product_id = parse_number(digits, 10, "product ID", offset)?;
+
+        if parser.offset < parser.serial.len() {
+            return Err(SerialError::Unexpected {
+                offset: parser.offset,
+            });
+        }
+
+        Ok(Self {
+            supplier,
+            year,
+            week,
+            product,
+            revision,
+            product_id,
+            stage,
+            variant,
+            pcb_design,
+            minor_rev,
+        })
+    }
+}
+
+impl FromStr for DeviceSerial {
+    type Err = SerialError;
+
+    /// Parse a serial, rejecting malformed serials
+    fn from_str(serial: &str) -> Result<Self, Self::Err> {
+        SerialFields::from_str(serial)?;
+        Ok(Self(serial.to_owned()))
+    }
+}
+
+impl DeviceSerial {
+    /// Decode the fields of the serial
+    pub fn fields(&self) -> Result<SerialFields, SerialError> {
+        self.0.parse()
+    }
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.serial.get(self.offset).copied()
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        let matched = self.peek() == Some(byte);
+        if matched {
+            self.offset += 1;
+        }
+
+        matched
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), SerialError> {
+        if self.eat(byte) {
+            Ok(())
+        } else {
+            Err(SerialError::Expected {
+                expected: char::from(byte),
+                offset:   self.offset,
+            })
+        }
+    }
+
+    /// Read a fixed width number
+    fn number(
+        &mut self,
+        width: usize,
+        radix: u32,
+        field: &'static str,
+    ) -> Result<u32, SerialError> {
+        let offset = self.offset;
+        let digits = self
+            .serial
+            .get(offset..offset + width)
+            .ok_or(SerialError::InvalidField { field, offset })?;
+
+        self.offset += width;
+        parse_number(digits, radix, field, offset)
+    }
+
+    /// Read a two digit hex number
+    fn hex_byte(&mut self, field: &'static str) -> Result<u8, SerialError> {
+        let offset = self.offset;
+        let number = self.number(2, 16, field)?;
+
+        u8::try_from(number).map_err(|_| SerialError::InvalidField { field, offset })
+    }
+
+    /// Read the decimal digits up to the next letter
+    fn digits(&mut self) -> &[u8] {
+        let start = self.offset;
+        while self.peek().is_some_and(|byte| byte.is_ascii_digit()) {
+            self.offset += 1;
+        }
+
+        &self.serial[start..self.offset]
+    }
+
+    /// Read the revision and the optional two digit minor revision following it
+    fn revision(&mut self) -> Result<(u32, Option<u32>), SerialError> {
+        let offset = self.offset;
+        let digits = self.digits();
+        if digits.is_empty() {
+            return Err(SerialError::InvalidField {
+                field: "revision",
+                offset,
+            });
+        }
+
+        if digits.len() <= 2 {
+            return Ok((parse_number(digits, 10, "revision", offset)?, None));
+        }
+
+        let (revision, minor_rev) = digits.split_at(digits.len() - 2);
+        Ok((
+            parse_number(revision, 10, "revision", offset)?,
+            Some(parse_number(
+                minor_rev,
+                10,
+                "minor revision",
+                offset + revision.len(),
+            )?),
+        ))
+    }
+
+    fn stage(&mut self) -> Result<Stage, SerialError> {
+        let offset = self.offset;
+        let stage = match self.peek() {
+            Some(b'H') => Stage::H,
+            Some(b'P') => Stage::P,
+            Some(b'T') => Stage::T,
+            Some(b'E') => Stage::E,
+            Some(b'X') => Stage::X,
+            _ => return Err(SerialError::InvalidStage { offset }),
+        };
+
+        self.offset += 1;
+        Ok(stage)
+    }
+}
+
+/* Helpers */
+
+fn parse_number(
+    digits: &[u8],
+    radix: u32,
+    field: &'static str,
+    offset: usize,
+) -> Result<u32, SerialError> {
+    // Reject signs and lowercase hex, the formatter never produces them
+    let valid = digits.iter().all(|byte| match radix {
+        16 => byte.is_ascii_digit() || (b'A'..=b'F').contains(byte),
+        _ => byte.is_ascii_digit(),
+    });
+
+    std::str::from_utf8(digits)
+        .ok()
+        .filter(|_| valid && !digits.is_empty())
+        .and_then(|digits| u32::from_str_radix(digits, radix).ok())
+        .ok_or(SerialError::InvalidField { field, offset })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_serials() {
+        let fields = "A02B2106W031H00418".parse::<SerialFields>().unwrap();
+        assert_eq!(
+            fields,
+            SerialFields {
+                supplier: 2,
+                year: 21,
+                week: 6,
+                product: 3,
+                revision: 1,
+                product_id: 418,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn parses_optional_fields() {
+        let fields = "A02B2412W0AT03205S01T01234"
+            .parse::<SerialFields>()
+            .unwrap();
+        assert_eq!(fields.product, 0x0A);
+        assert_eq!(fields.pcb_design, Some(3));
+        assert_eq!(fields.revision, 2);
+        assert_eq!(fields.minor_rev, Some(5));
+        assert_eq!(fields.variant, Some(1));
+        assert_eq!(fields.stage, Stage::T);
+        assert_eq!(fields.product_id, 1234);
+    }
+
+    #[test]
+    fn round_trips_with_the_formatter() {
+        let devices = [
+            Device::default(),
+            Device {
+                supplier: 0x1F,
+                year: 24,
+                week: 52,
+                product: 0xC4,
+                revision: 12,
+                product_id: 123_456,
+                stage: Stage::X,
+                variant: Some(3),
+                pcb_design: Some(10),
+                minor_rev: Some(0),
+                ..Default::default()
+            },
+            Device {
+                revision: 7,
+                stage: Stage::P,
+                minor_rev: Some(99),
+                ..Default::default()
+            },
+        ];
+
+        for device in devices {
+            let device_serial = DeviceSerial::from(&device);
+            let fields = device_serial.fields().unwrap();
+            assert_eq!(fields, SerialFields::from(&device));
+            assert_eq!(fields.to_string(), device_serial.to_string());
+        }
+    }
+
+    #[test]
+    fn round_trips_hex_fields_at_the_boundary() {
+        for byte in [0, 0x0F, 0x10, u8::MAX] {
+            let device = Device {
+                supplier: byte,
+                product: byte,
+                ..Default::default()
+            };
+
+            let device_serial = DeviceSerial::from(&device);
+            assert_eq!(device_serial.fields().unwrap(), SerialFields::from(&device));
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_serials() {
+        let cases = [
+            (
+                "",
+                SerialError::Expected {
+                    expected: 'A',
+                    offset:   0,
+                },
+            ),
+            (
+                "A02C2106W031H00418",
+                SerialError::Expected {
+                    expected: 'B',
+                    offset:   3,
+                },
+            ),
+            (
+                "A0GB2106W031H00418",
+                SerialError::InvalidField {
+                    field:  "supplier",
+                    offset: 1,
+                },
+            ),
+            (
+                "A02B2106W03H00418",
+                SerialError::InvalidField {
+                    field:  "revision",
+                    offset: 11,
+                },
+            ),
+            (
+                "A02B2106W031Q00418",
+                SerialError::InvalidStage { offset: 12 },
+            ),
+            (
+                "A02B2106W031H0418",
+                SerialError::InvalidField {
+                    field:  "product ID",
+                    offset: 13,
+                },
+            ),
+            (
+                "A02B2106W031H00418 ",
+                SerialError::Unexpected { offset: 18 },
+            ),
+        ];
+
+        for (serial, error) in cases {
+            assert_eq!(serial.parse::<DeviceSerial>(), Err(error), "{serial}");
+        }
+    }
+}
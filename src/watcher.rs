@@ -0,0 +1,314 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        mpsc::{self, Receiver},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+
+use crate::{run, Device, DeviceManager, DeviceSerial, ProfileIndex, Result};
+
+/* Structures */
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DeviceEvent {
+    /// A keyboard was plugged in or woke up
+    Connected {
+        device:        Device,
+        profile_index: Option<ProfileIndex>,
+    },
+    /// A keyboard was unplugged or went to sleep
+    Disconnected { serial: DeviceSerial },
+    /// The active profile of a keyboard changed, by any program or the profile key
+    ProfileChanged {
+        serial: DeviceSerial,
+        from:   ProfileIndex,
+        to:     ProfileIndex,
+    },
+}
+
+/// Options controlling how often the watcher polls the keyboards
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WatchOptions {
+    /// Interval between checks for connected and disconnected keyboards
+    pub hotplug_interval: Duration,
+    /// Interval between reads of the active profile of every keyboard
+    pub profile_interval: Duration,
+}
+
+/// The last observed keyboards and their active profiles
+///
+/// This is kept apart from the device manager cache, which any command may rescan.
+#[derive(Debug, Default)]
+struct Watcher {
+    devices: HashMap<DeviceSerial, Option<ProfileIndex>>,
+}
+
+/* Implementations */
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        Self {
+            hotplug_interval: Duration::from_secs(1),
+            profile_interval: Duration::from_millis(250),
+        }
+    }
+}
+
+impl Watcher {
+    /// Start from the cached devices so already connected keyboards aren't reported
+    fn new(manager: &mut DeviceManager) -> Self {
+        let devices = manager.profile_indices().and_then(|profile_indices| {
+            let devices = manager
+                .devices()?
+                .iter()
+                .map(DeviceSerial::from)
+                .map(|device_serial| {
+                    let profile_index = profile_indices.get(&device_serial).copied();
+                    (device_serial, profile_index)
+                })
+                .collect();
+
+            Ok(devices)
+        });
+
+        Self {
+            devices: devices.unwrap_or_default(),
+        }
+    }
+
+    /// Read the active profiles, and check for connected and disconnected devices first if
+    /// `hotplug` is set
+    fn poll(
+        &mut self,
+        manager: &mut DeviceManager,
+        hotplug: bool,
+    ) -> Vec<Result<Vec<DeviceEvent>>> {
+        let mut results = Vec::new();
+        if hotplug {
+            results.push(self.poll_devices(manager));
+        }
+
+        results.push(self.poll_profiles(manager));
+        results
+    }
+
+    /// Report which devices were connected or disconnected, the devices are only rescanned
+    /// when a keyboard was connected, disconnected or swapped
+    fn poll_devices(&mut self, manager: &mut DeviceManager) -> Result<Vec<DeviceEvent>> {
+        manager.refresh()?;
+        let mut devices = manager
+            .devices()?
+            .into_iter()
+            .map(|device| (DeviceSerial::from(&device), device))
+            .collect::<HashMap<_, _>>();
+
+        let mut events = Vec::new();
+        self.devices.retain(|device_serial, _| {
+            let connected = devices.remove(device_serial).is_some();
+            if !connected {
+                events.push(DeviceEvent::Disconnected {
+                    serial: device_serial.clone(),
+                });
+            }
+
+            connected
+        });
+
+        if devices.is_empty() {
+            return Ok(events);
+        }
+
+        let profile_indices = manager.profile_indices()?;
+        for (device_serial, device) in devices {
+            let profile_index = profile_indices.get(&device_serial).copied();
+            self.devices.insert(device_serial, profile_index);
+            events.push(DeviceEvent::Connected {
+                device,
+                profile_index,
+            });
+        }
+
+        Ok(events)
+    }
+
+    /// Read the active profiles and report which changed since the last read
+    fn poll_profiles(&mut self, manager: &mut DeviceManager) -> Result<Vec<DeviceEvent>> {
+        let mut events = Vec::new();
+        for (device_serial, to) in manager.profile_indices()? {
+            // Keyboards are only tracked once they have been reported as connected
+            let Some(profile_index) = self.devices.get_mut(&device_serial) else {
+                continue;
+            };
+
+            if let Some(from) = profile_index.replace(to).filter(|from| *from != to) {
+                events.push(DeviceEvent::ProfileChanged {
+                    serial: device_serial,
+                    from,
+                    to,
+                });
+            }
+        }
+
+        Ok(events)
+    }
+}
+
+/* Functions */
+
+/// Watch the keyboards for connections, disconnections and profile changes
+///
+/// The keyboards are polled on the device worker, between other commands. Keyboards that
+/// are already connected when watching starts are not reported. The watcher stops once
+/// the receiver is dropped.
+///
+/// # Panics
+///
+/// Panics if the watcher thread can't be spawned
+#[must_use]
+pub fn watch(options: &WatchOptions) -> Receiver<DeviceEvent> {
+    let (sender, receiver) = mpsc::channel();
+    let options = options.clone();
+
+    std::thread::Builder::new()
+        .name(String::from("device-watcher"))
+        .spawn(move || {
+            let watcher = run(|manager| Ok(Watcher::new(manager))).unwrap_or_default();
+            let watcher = Arc::new(Mutex::new(watcher));
+            let mut last_hotplug = Instant::now();
+
+            loop {
+                std::thread::sleep(options.profile_interval);
+
+                let hotplug = last_hotplug.elapsed() >= options.hotplug_interval;
+                if hotplug {
+                    last_hotplug = Instant::now();
+                }
+
+                let watcher = Arc::clone(&watcher);
+                let Ok(results) = run(move |manager| Ok(watcher.lock().poll(manager, hotplug)))
+                else {
+                    return;
+                };
+
+                for result in results {
+                    let events = match result {
+                        Ok(events) => events,
+                        Err(error) => {
+                            eprintln!("Failed to poll devices: {error}");
+                            continue;
+                        }
+                    };
+
+                    for event in events {
+                        if sender.send(event).is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+        })
+        .expect("Failed to spawn device watcher thread");
+
+    receiver
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SimulatedBackend, SimulatedDevice};
+
+    fn watched(device_count: u8) -> (Watcher, DeviceManager, SimulatedBackend) {
+        let backend = SimulatedBackend::new(device_count);
+        let mut manager = DeviceManager::new(Box::new(backend.clone()));
+        let watcher = Watcher::new(&mut manager);
+
+        (watcher, manager, backend)
+    }
+
+    fn device_serial(manager: &mut DeviceManager, slot: usize) -> DeviceSerial {
+        DeviceSerial::from(&manager.devices().unwrap()[slot])
+    }
+
+    #[test]
+    fn reports_nothing_without_changes() {
+        let (mut watcher, mut manager, _) = watched(2);
+
+        assert!(watcher.poll_devices(&mut manager).unwrap().is_empty());
+        assert!(watcher.poll_profiles(&mut manager).unwrap().is_empty());
+    }
+
+    #[test]
+    fn reports_profile_changes() {
+        let (mut watcher, mut manager, backend) = watched(2);
+        let device_serial = device_serial(&mut manager, 1);
+        backend.state().lock().devices[1].profile_index = 2;
+
+        let events = watcher.poll_profiles(&mut manager).unwrap();
+        assert_eq!(
+            events,
+            [DeviceEvent::ProfileChanged {
+                serial: device_serial,
+                from:   0,
+                to:     2,
+            }]
+        );
+        assert!(watcher.poll_profiles(&mut manager).unwrap().is_empty());
+    }
+
+    #[test]
+    fn reports_disconnected_and_reconnected_devices() {
+        let (mut watcher, mut manager, backend) = watched(2);
+        let device = manager.devices().unwrap().remove(1);
+        let device_serial = DeviceSerial::from(&device);
+        backend.state().lock().devices[1].connected = false;
+
+        let events = watcher.poll_devices(&mut manager).unwrap();
+        assert_eq!(
+            events,
+            [DeviceEvent::Disconnected {
+                serial: device_serial,
+            }]
+        );
+
+        backend.state().lock().devices[1].profile_index = 3;
+        backend.state().lock().devices[1].connected = true;
+
+        let events = watcher.poll_devices(&mut manager).unwrap();
+        assert_eq!(
+            events,
+            [DeviceEvent::Connected {
+                device,
+                profile_index: Some(3),
+            }]
+        );
+    }
+
+    #[test]
+    fn reports_a_device_replaced_at_the_same_count() {
+        let (mut watcher, mut manager, backend) = watched(2);
+        let replaced = device_serial(&mut manager, 1);
+        backend.state().lock().devices[1] = SimulatedDevice::new(2);
+
+        let events = watcher.poll_devices(&mut manager).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0], DeviceEvent::Disconnected { serial: replaced });
+        assert!(matches!(
+            &events[1],
+            DeviceEvent::Connected { device, .. } if device.model_name == "Wooting 80HE"
+        ));
+    }
+
+    #[test]
+    fn ignores_profile_changes_of_unreported_devices() {
+        let (mut watcher, mut manager, backend) = watched(0);
+        backend.state().lock().devices.push(SimulatedDevice::new(0));
+        manager.rescan().unwrap();
+        backend.state().lock().devices[0].profile_index = 1;
+
+        assert!(watcher.poll_profiles(&mut manager).unwrap().is_empty());
+    }
+}
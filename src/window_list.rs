@@ -0,0 +1,325 @@
+use std::{ffi::OsStr, path::Path};
+
+use anyhow::Result;
+
+/* Structures */
+
+/// A visible top-level window and the process owning it
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct OpenWindow {
+    pub app_name: String,
+    pub bin_name: String,
+    pub bin_path: String,
+    pub win_name: String,
+    pub pid:      u32,
+    /// The `WM_CLASS` class of the window, only on X11
+    pub class:    Option<String>,
+}
+
+/* Implementations */
+
+#[cfg_attr(not(any(target_os = "windows", target_os = "linux")), allow(dead_code))]
+impl OpenWindow {
+    fn new(win_name: String, pid: u32, bin_path: &Path) -> Self {
+        let bin_name = bin_path
+            .file_name()
+            .and_then(OsStr::to_str)
+            .map(String::from)
+            .unwrap_or_default();
+
+        // Matches what the active window reports when no better name is known
+        let app_name = bin_path
+            .file_stem()
+            .and_then(OsStr::to_str)
+            .map(String::from)
+            .unwrap_or_default();
+
+        Self {
+            app_name,
+            bin_name,
+            bin_path: bin_path.display().to_string(),
+            win_name,
+            pid,
+            class: None,
+        }
+    }
+}
+
+/* Functions */
+
+// Lists the visible top-level windows, sorted by process and title
+pub fn open_windows() -> Result<Vec<OpenWindow>> {
+    let mut windows = platform::open_windows()?;
+    windows.retain(|window| !window.win_name.is_empty());
+    windows.sort_by(|a, b| {
+        (a.bin_name.to_lowercase(), &a.win_name).cmp(&(b.bin_name.to_lowercase(), &b.win_name))
+    });
+
+    Ok(windows)
+}
+
+/* Backend */
+
+#[cfg(target_os = "windows")]
+mod platform {
+    use std::path::{Path, PathBuf};
+
+    use anyhow::Result;
+    use windows::{
+        core::{BOOL, HSTRING, PWSTR},
+        Win32::{
+            Foundation::{CloseHandle, HWND, LPARAM},
+            Storage::FileSystem::{GetFileVersionInfoSizeW, GetFileVersionInfoW, VerQueryValueW},
+            System::Threading::{
+                OpenProcess,
+                QueryFullProcessImageNameW,
+                PROCESS_NAME_WIN32,
+                PROCESS_QUERY_LIMITED_INFORMATION,
+            },
+            UI::WindowsAndMessaging::{
+                EnumWindows,
+                GetWindow,
+                GetWindowTextLengthW,
+                GetWindowTextW,
+                GetWindowThreadProcessId,
+                IsWindowVisible,
+                GW_OWNER,
+            },
+        },
+    };
+
+    use super::OpenWindow;
+
+    pub fn open_windows() -> Result<Vec<OpenWindow>> {
+        let mut handles = Vec::<HWND>::new();
+        unsafe {
+            EnumWindows(
+                Some(enum_window),
+                LPARAM(std::ptr::from_mut(&mut handles) as isize),
+            )?;
+        }
+
+        Ok(handles
+            .into_iter()
+            .filter_map(|hwnd| {
+                let mut pid = 0;
+                unsafe { GetWindowThreadProcessId(hwnd, Some(&mut pid)) };
+                let bin_path = process_path(pid)?;
+
+                let mut open_window = OpenWindow::new(window_text(hwnd), pid, &bin_path);
+                if let Some(app_name) = file_description(&bin_path) {
+                    open_window.app_name = app_name;
+                }
+
+                Some(open_window)
+            })
+            .collect())
+    }
+
+    // Collects visible unowned windows, owned windows are dialogs and tool windows
+    unsafe extern "system" fn enum_window(hwnd: HWND, lparam: LPARAM) -> BOOL {
+        let handles = &mut *(lparam.0 as *mut Vec<HWND>);
+        let owned = GetWindow(hwnd, GW_OWNER).is_ok_and(|owner| !owner.is_invalid());
+        if IsWindowVisible(hwnd).as_bool() && !owned {
+            handles.push(hwnd);
+        }
+
+        true.into()
+    }
+
+    fn window_text(hwnd: HWND) -> String {
+        let length = unsafe { GetWindowTextLengthW(hwnd) };
+        let mut buffer = vec![0u16; usize::try_from(length).unwrap_or_default() + 1];
+        let copied = unsafe { GetWindowTextW(hwnd, &mut buffer) };
+
+        String::from_utf16_lossy(&buffer[..usize::try_from(copied).unwrap_or_default()])
+    }
+
+    fn process_path(pid: u32) -> Option<PathBuf> {
+        let handle = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid) }.ok()?;
+        let mut buffer = [0u16; 1024];
+        let mut length = u32::try_from(buffer.len()).unwrap_or_default();
+        let result = unsafe {
+            QueryFullProcessImageNameW(
+                handle,
+                PROCESS_NAME_WIN32,
+                PWSTR(buffer.as_mut_ptr()),
+                &mut length,
+            )
+        };
+        let _ = unsafe { CloseHandle(handle) };
+        result.ok()?;
+
+        let length = usize::try_from(length).ok()?;
+        Some(PathBuf::from(String::from_utf16_lossy(&buffer[..length])))
+    }
+
+    // The app name active-win-pos-rs reports, it prefers the description over the file stem
+    fn file_description(bin_path: &Path) -> Option<String> {
+        let file_name = HSTRING::from(bin_path.as_os_str());
+        let size = unsafe { GetFileVersionInfoSizeW(&file_name, None) };
+        if size == 0 {
+            return None;
+        }
+
+        let mut info = vec![0u8; usize::try_from(size).ok()?];
+        unsafe { GetFileVersionInfoW(&file_name, None, size, info.as_mut_ptr().cast()) }.ok()?;
+
+        // Pairs of language and code page, the length is in bytes
+        let (translations, length) = query_value(&info, "\\VarFileInfo\\Translation")?;
+        let translations = unsafe { std::slice::from_raw_parts(translations, length / 2) };
+
+        translations.chunks_exact(2).find_map(|translation| {
+            let key = format!(
+                "\\StringFileInfo\\{:04x}{:04x}\\FileDescription",
+                translation[0], translation[1]
+            );
+
+            // The length of strings is in characters, including the null terminator
+            let (description, length) = query_value(&info, &key)?;
+            let description = unsafe { std::slice::from_raw_parts(description, length) };
+            let description = String::from_utf16_lossy(description);
+            let description = description.trim_matches('\0');
+
+            (!description.is_empty()).then(|| description.to_owned())
+        })
+    }
+
+    fn query_value(info: &[u8], key: &str) -> Option<(*const u16, usize)> {
+        let mut buffer = std::ptr::null_mut();
+        let mut length = 0;
+        let found = unsafe {
+            VerQueryValueW(
+                info.as_ptr().cast(),
+                &HSTRING::from(key),
+                &mut buffer,
+                &mut length,
+            )
+        };
+
+        (found.as_bool() && !buffer.is_null()).then(|| {
+            (
+                buffer.cast_const().cast(),
+                usize::try_from(length).unwrap_or_default(),
+            )
+        })
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use std::path::PathBuf;
+
+    use anyhow::{anyhow, Result};
+    use xcb::{x, Connection};
+
+    use super::OpenWindow;
+
+    pub fn open_windows() -> Result<Vec<OpenWindow>> {
+        let (conn, screen_num) = Connection::connect(None)?;
+        let root = conn
+            .get_setup()
+            .roots()
+            .nth(usize::try_from(screen_num)?)
+            .ok_or_else(|| anyhow!("Couldn't find the X11 screen"))?
+            .root();
+
+        // The window manager keeps the managed top-level windows in _NET_CLIENT_LIST
+        let client_list = intern_atom(&conn, b"_NET_CLIENT_LIST")?;
+        let net_wm_name = intern_atom(&conn, b"_NET_WM_NAME")?;
+        let net_wm_pid = intern_atom(&conn, b"_NET_WM_PID")?;
+
+        let windows = get_property(&conn, root, client_list, 32)?
+            .ok_or_else(|| anyhow!("The window manager doesn't list its windows"))?
+            .value::<x::Window>()
+            .to_vec();
+
+        Ok(windows
+            .into_iter()
+            .filter_map(|window| {
+                let pid = *get_property(&conn, window, net_wm_pid, 32)
+                    .ok()??
+                    .value::<u32>()
+                    .first()?;
+                let bin_path = std::fs::read_link(format!("/proc/{pid}/exe"))
+                    .unwrap_or_else(|_| PathBuf::from(process_name(pid).unwrap_or_default()));
+
+                let mut win_name = get_string(&conn, window, net_wm_name);
+                if win_name.is_empty() {
+                    win_name = get_string(&conn, window, x::ATOM_WM_NAME);
+                }
+
+                // WM_CLASS holds the instance and class names, each null terminated
+                let class = get_string(&conn, window, x::ATOM_WM_CLASS)
+                    .split('\0')
+                    .nth(1)
+                    .filter(|class| !class.is_empty())
+                    .map(String::from);
+
+                let mut open_window = OpenWindow::new(win_name, pid, &bin_path);
+                if let Some(class) = &class {
+                    open_window.app_name.clone_from(class);
+                }
+                open_window.class = class;
+
+                Some(open_window)
+            })
+            .collect())
+    }
+
+    fn intern_atom(conn: &Connection, name: &[u8]) -> Result<x::Atom> {
+        let cookie = conn.send_request(&x::InternAtom {
+            only_if_exists: true,
+            name,
+        });
+
+        Ok(conn.wait_for_reply(cookie)?.atom())
+    }
+
+    // None if the property isn't set or its values aren't `format` bits wide, reading the
+    // values with another width would panic
+    fn get_property(
+        conn: &Connection,
+        window: x::Window,
+        property: x::Atom,
+        format: u8,
+    ) -> Result<Option<x::GetPropertyReply>> {
+        let cookie = conn.send_request(&x::GetProperty {
+            delete: false,
+            window,
+            property,
+            r#type: x::ATOM_ANY,
+            long_offset: 0,
+            long_length: u32::MAX,
+        });
+
+        let reply = conn.wait_for_reply(cookie)?;
+        let set = reply.r#type() != x::ATOM_NONE && reply.format() == format;
+
+        Ok(set.then_some(reply))
+    }
+
+    fn get_string(conn: &Connection, window: x::Window, property: x::Atom) -> String {
+        get_property(conn, window, property, 8)
+            .ok()
+            .flatten()
+            .map(|reply| String::from_utf8_lossy(reply.value::<u8>()).into_owned())
+            .unwrap_or_default()
+    }
+
+    fn process_name(pid: u32) -> Option<String> {
+        let comm = std::fs::read_to_string(format!("/proc/{pid}/comm")).ok()?;
+        Some(comm.trim_end().to_owned())
+    }
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "linux")))]
+mod platform {
+    use anyhow::{bail, Result};
+
+    use super::OpenWindow;
+
+    pub fn open_windows() -> Result<Vec<OpenWindow>> {
+        bail!("Listing windows isn't supported on this platform")
+    }
+}
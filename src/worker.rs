@@ -0,0 +1,108 @@
+use std::{
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError},
+        LazyLock,
+    },
+    time::Instant,
+};
+
+use crate::{DeviceManager, Error, Result, MANAGER};
+
+/* Typings */
+
+type Job = Box<dyn FnOnce(&mut DeviceManager) + Send>;
+
+/* Statics */
+
+/// All device I/O runs on this thread so commands from different callers never interleave
+///
+/// Between jobs the thread wakes up to end the flashes showing on the devices.
+static WORKER: LazyLock<Sender<Job>> = LazyLock::new(|| {
+    let (sender, receiver) = mpsc::channel::<Job>();
+    std::thread::Builder::new()
+        .name(String::from("device-worker"))
+        .spawn(move || loop {
+            let next_flash_end = MANAGER.lock().next_flash_end();
+            let job = match next_flash_end {
+                Some(ends) => {
+                    match receiver.recv_timeout(ends.saturating_duration_since(Instant::now())) {
+                        Ok(job) => Some(job),
+                        Err(RecvTimeoutError::Timeout) => None,
+                        Err(RecvTimeoutError::Disconnected) => return,
+                    }
+                }
+                None => match receiver.recv() {
+                    Ok(job) => Some(job),
+                    Err(_) => return,
+                },
+            };
+
+            let mut manager = MANAGER.lock();
+            manager.end_flashes(Instant::now());
+            if let Some(job) = job {
+                job(&mut manager);
+            }
+            drop(manager);
+        })
+        .expect("Failed to spawn device worker thread");
+
+    sender
+});
+
+/* Structures */
+
+/// The completion result of a queued device job
+#[derive(Debug)]
+pub struct Pending<T> {
+    receiver: Receiver<Result<T>>,
+}
+
+/* Implementations */
+
+impl<T> Pending<T> {
+    /// Block until the job has completed
+    pub fn wait(self) -> Result<T> {
+        self.receiver.recv().unwrap_or(Err(Error::WorkerStopped))
+    }
+
+    /// The result of the job if it has completed
+    #[must_use]
+    pub fn try_wait(&self) -> Option<Result<T>> {
+        match self.receiver.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(Error::WorkerStopped)),
+        }
+    }
+}
+
+/* Functions */
+
+/// Queue a job on the device worker thread without waiting for it
+///
+/// Jobs run one at a time in the order they were queued, a job may issue any number of
+/// commands without another caller's commands landing in between.
+pub fn submit<T, F>(job: F) -> Pending<T>
+where
+    T: Send + 'static,
+    F: FnOnce(&mut DeviceManager) -> Result<T> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::sync_channel(1);
+    let job: Job = Box::new(move |manager| {
+        let _ = sender.send(job(manager));
+    });
+
+    // The receiver reports the worker stopped if the job can't be queued
+    let _ = WORKER.send(job);
+
+    Pending { receiver }
+}
+
+/// Run a job on the device worker thread and wait for its result
+pub fn run<T, F>(job: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce(&mut DeviceManager) -> Result<T> + Send + 'static,
+{
+    submit(job).wait()
+}